@@ -7,6 +7,8 @@ pub struct Actor {
     pub pos: IVec2,
     pub vel: Vec2,
     pub colliding: Option<f32>,
+    /// Part of the actor atoms that are liquid, from 0 to 1, updated before it moves
+    pub submerged: f32,
 }
 
 //Called before simulations
@@ -14,21 +16,49 @@ pub fn fill_actors(
     mut chunk_manager: ResMut<ChunkManager>,
     actors: Query<(&Actor, Entity)>,
     mut dirty_rects: ResMut<DirtyRects>,
+    mut particles: ResMut<Particles>,
     materials: (Res<Assets<Materials>>, Res<MaterialsHandle>),
     mut ev_damage: EventWriter<DamageEvent>,
 ) {
     let materials = materials.0.get(&materials.1 .0).unwrap();
-    let mut damages = 0.;
 
     for (actor, ent) in actors.iter() {
+        let speed = actor.vel.length();
+        let mut damages = 0.;
+
         for x_off in 0..actor.width as i32 {
-            for y_off in 0..actor.height as i32 {
-                let pos = global_to_chunk(actor.pos + ivec2(x_off, y_off));
+            //From the bottom, so the atoms above are still unfilled
+            for y_off in (0..actor.height as i32).rev() {
+                let global = actor.pos + ivec2(x_off, y_off);
+                let pos = global_to_chunk(global);
+                let surface = chunk_manager
+                    .get_atom(&global_to_chunk(global - IVec2::Y))
+                    .is_some_and(|atom| atom.is_void());
+
                 if let Some(atom) = chunk_manager.get_mut_atom(pos) {
-                    if atom.is_void() {
+                    //Moving fast pushes the liquid surface aside, splashing it
+                    if atom.is_liquid()
+                        && surface
+                        && speed >= ACTOR_SPLASH_SPEED
+                        && fastrand::f32() < ACTOR_SPLASH_CHANCE
+                    {
+                        let angle = (fastrand::f32() * 2. - 1.) * SPLASH_SPREAD;
+                        particles.spawn(Particle {
+                            atom: *atom,
+                            velocity: Vec2::from_angle(angle).rotate(-Vec2::Y)
+                                * speed
+                                * SPLASH_SPEED
+                                + vec2(actor.vel.x * SPLASH_SPEED, 0.),
+                            pos: global.as_vec2(),
+                            ..Default::default()
+                        });
+                        *atom = Atom::object();
+                        update_dirty_rects(&mut dirty_rects.render, pos);
+                    } else if atom.is_void() {
                         *atom = Atom::object();
                     } else if materials[atom.id].damage > 0. {
-                        damages += materials[atom.id].damage / (actor.width * actor.height) as f32;
+                        damages +=
+                            materials[atom.id].damage / (actor.width as f32 * actor.height as f32);
                     }
                 }
                 update_dirty_rects_3x3(&mut dirty_rects.current, pos);
@@ -73,6 +103,44 @@ pub fn on_ground(chunk_manager: &ChunkManager, actor: &Actor) -> bool {
     false
 }
 
+/// Part of the actor atoms that are liquid, from 0 to 1,
+/// and the density of the liquids times that part
+pub fn liquid_in(chunk_manager: &ChunkManager, actor: &Actor, materials: &Materials) -> (f32, f32) {
+    let (mut liquid, mut density) = (0, 0.);
+    for x_off in 0..actor.width as i32 {
+        for y_off in 0..actor.height as i32 {
+            let chunk_pos = global_to_chunk(actor.pos + ivec2(x_off, y_off));
+            if let Some(atom) = chunk_manager.get_atom(&chunk_pos) {
+                if atom.is_liquid() {
+                    liquid += 1;
+                    density += materials[atom.id].density;
+                }
+            }
+        }
+    }
+
+    let size = actor.width as f32 * actor.height as f32;
+    (liquid as f32 / size, density / size)
+}
+
+/// Buoyancy and drag of the liquids the actors are in
+pub fn liquid_forces(
+    chunk_manager: Res<ChunkManager>,
+    mut actors: Query<&mut Actor>,
+    materials: (Res<Assets<Materials>>, Res<MaterialsHandle>),
+) {
+    let materials = materials.0.get(&materials.1 .0).unwrap();
+
+    for mut actor in actors.iter_mut() {
+        let (submerged, density) = liquid_in(&chunk_manager, &actor, materials);
+        actor.submerged = submerged;
+        if density > 0. {
+            actor.vel.y -= ACTOR_BUOYANCY * density;
+            actor.vel *= 1. - (ACTOR_LIQUID_DRAG * density).min(1.);
+        }
+    }
+}
+
 pub fn update_actors(mut chunk_manager: ResMut<ChunkManager>, mut actors: Query<&mut Actor>) {
     for mut actor in actors.iter_mut() {
         actor.colliding = None;
@@ -200,6 +268,11 @@ impl Plugin for ActorsPlugin {
                     .before(chunk_manager_update)
                     .before(update_particles),
                 unfill_actors.after(chunk_manager_update),
+                //Before the velocities are set, so swimming isn't slowed down
+                liquid_forces
+                    .after(unfill_actors)
+                    .before(update_player)
+                    .before(update_creatures),
                 update_actors.after(unfill_actors),
             )
                 .run_if(in_state(GameState::Game)),
@@ -1,4 +1,5 @@
-use rand::Rng;
+use itertools::Itertools;
+use rand::{seq::SliceRandom, Rng};
 use std::collections::HashSet;
 use std::f32::consts::PI;
 
@@ -133,6 +134,34 @@ impl Atom {
                     255,
                 ];
             }
+            11 => {
+                //Wood
+                atom.color = [
+                    (110 + rand::rng().random_range(-10_i16..10_i16)) as u8,
+                    (70 + rand::rng().random_range(-10_i16..10_i16)) as u8,
+                    (37 + rand::rng().random_range(-5_i16..5_i16)) as u8,
+                    255,
+                ];
+            }
+            12 => {
+                //Metal
+                let shade = rand::rng().random_range(-8_i16..8_i16);
+                atom.color = [
+                    (150 + shade) as u8,
+                    (155 + shade) as u8,
+                    (165 + shade) as u8,
+                    255,
+                ];
+            }
+            13 => {
+                //Glass
+                atom.color = [
+                    (190 + rand::rng().random_range(-5_i16..5_i16)) as u8,
+                    (225 + rand::rng().random_range(-5_i16..5_i16)) as u8,
+                    (235 + rand::rng().random_range(-5_i16..5_i16)) as u8,
+                    170,
+                ];
+            }
             _ => panic!("Atom not found, invalid ID. {id}"),
         }
 
@@ -199,6 +228,11 @@ pub fn update_powder(
         }
 
         if !swapped {
+            awakened.extend(splash(chunks, cur_pos, ivec2(0, speed as i32)));
+            if get_atom(chunks, cur_pos).is_void() {
+                break;
+            }
+
             let vel = Vec2::new(0.0, -(speed as f32));
 
             set_vel(
@@ -236,6 +270,7 @@ pub fn update_liquid(
     let mut swapped = false;
     for _ in 0..speed {
         let neigh = down_neigh(chunks, cur_pos, &[], dt);
+        let mut fell = false;
         for neigh in neigh {
             if neigh.0 {
                 swap(chunks, cur_pos, cur_pos + neigh.1, dt);
@@ -243,10 +278,21 @@ pub fn update_liquid(
                 cur_pos += neigh.1;
                 awakened.insert(cur_pos);
                 swapped = true;
+                fell = true;
 
                 break;
             }
         }
+
+        //Landed, so it stops falling
+        if !fell {
+            awakened.extend(splash(chunks, cur_pos, ivec2(0, speed as i32)));
+            if get_atom(chunks, cur_pos).is_void() {
+                return awakened;
+            }
+            set_speed(chunks, cur_pos, 0);
+            break;
+        }
     }
 
     if !swapped {
@@ -302,8 +348,14 @@ pub fn update_atom(chunks: &mut UpdateChunksType, pos: IVec2, dt: u8) -> HashSet
             awakened.insert(pos);
             set_vel(chunks, pos, vel * 4 / 5);
             set_vel(chunks, cur_pos, vel / 5);
+            awakened.extend(splash(chunks, cur_pos, vel));
             break;
         } else {
+            awakened.extend(splash(chunks, cur_pos, vel));
+            if get_atom(chunks, cur_pos).is_void() {
+                break;
+            }
+
             if vel.abs().x > 4 && vel.abs().y > 4 {
                 set_vel(
                     chunks,
@@ -319,3 +371,78 @@ pub fn update_atom(chunks: &mut UpdateChunksType, pos: IVec2, dt: u8) -> HashSet
 
     awakened
 }
+
+/// Splashes atoms around a impact of the atom on `pos` as particles, more and faster
+/// the faster the impact and the splashier the material. Returns atoms awakened
+pub fn splash(chunks: &mut UpdateChunksType, pos: IVec2, vel: IVec2) -> HashSet<IVec2> {
+    let mut awakened = HashSet::new();
+
+    let materials = chunks.materials;
+    let splash = materials[&get_atom(chunks, pos)].splash;
+    let speed = vel.as_vec2().length();
+    if splash <= 0. || speed < SPLASH_MIN_SPEED {
+        return awakened;
+    }
+
+    //The fraction is the chance of splashing one more atom
+    let count = (speed - SPLASH_MIN_SPEED) * splash;
+    let count = count as usize + (rand::rng().random::<f32>() < count.fract()) as usize;
+    if count == 0 {
+        return awakened;
+    }
+
+    //Splashy atoms on the surface around the impact
+    let mut surface = vec![];
+    for (y, x) in (-SPLASH_RADIUS..=SPLASH_RADIUS).cartesian_product(-SPLASH_RADIUS..=SPLASH_RADIUS)
+    {
+        let pos = pos + ivec2(x, y);
+        let (Some(atom), Some(above)) = (
+            chunks.group.get_global(pos),
+            chunks.group.get_global(pos - IVec2::Y),
+        ) else {
+            continue;
+        };
+
+        if (atom.is_liquid() || atom.is_powder()) && materials[atom].splash > 0. && above.is_void()
+        {
+            surface.push(pos);
+        }
+    }
+    surface.shuffle(&mut rand::rng());
+
+    for pos in surface.into_iter().take(count.min(SPLASH_MAX_ATOMS)) {
+        let atom = get_atom(chunks, pos);
+        let chunk_pos = group_to_chunk_pos(chunks, pos);
+
+        let angle = rand::rng().random_range(-SPLASH_SPREAD..SPLASH_SPREAD);
+        let velocity = Vec2::from_angle(angle).rotate(-Vec2::Y)
+            * speed
+            * SPLASH_SPEED
+            * rand::rng().random_range(0.5..1.);
+
+        chunks
+            .splash_send
+            .try_send(Particle {
+                atom: Atom {
+                    speed: (0, 0),
+                    ..atom
+                },
+                velocity: velocity + vec2(vel.x as f32 * SPLASH_SPEED, 0.),
+                pos: chunk_pos.to_global().as_vec2(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        chunks.group[pos] = Atom::default();
+        chunks
+            .dirty_render_rect_send
+            .try_send(DeferredDirtyRectUpdate {
+                chunk_pos,
+                ..Default::default()
+            })
+            .unwrap();
+        awakened.insert(pos);
+    }
+
+    awakened
+}
@@ -0,0 +1,136 @@
+use crate::prelude::*;
+
+/// How a material color changes over time, applied when the chunk textures are uploaded
+#[derive(Default, Debug, Deserialize, PartialEq, Clone, Copy)]
+pub enum ColorAnimation {
+    #[default]
+    None,
+    /// Waves of brightness going through the atoms, like light on water
+    Shimmer { speed: f32, strength: f32 },
+    /// Random brightness changes on each atom, for hot materials
+    Flicker { speed: f32, strength: f32 },
+    /// Brightness going up and down, stronger while the atom is moving
+    Pulse { speed: f32, strength: f32 },
+}
+
+/// Rects of the animated atoms re-uploaded this frame. Chunks are split in tiles that take
+/// turns, so each one is only uploaded every `COLOR_ANIMATION_INTERVAL` frames and only
+/// the parts of a chunk with animated atoms are uploaded.
+#[derive(Resource, Default)]
+pub struct AnimatedRects {
+    pub rects: HashMap<IVec2, Vec<URect>>,
+    /// Animation of each material id
+    pub animations: Vec<ColorAnimation>,
+    pub time: f32,
+    frame: i32,
+    /// Bounds of the animated atoms of each chunk tile, none if it has none
+    bounds: HashMap<IVec2, Vec<Option<URect>>>,
+}
+
+impl AnimatedRects {
+    /// Gets the color of the atom at the global position, with it's material animation
+    pub fn color(&self, atom: &Atom, pos: IVec2) -> [u8; 4] {
+        let animation = self
+            .animations
+            .get(atom.id as usize)
+            .copied()
+            .unwrap_or_default();
+
+        let brightness = match animation {
+            ColorAnimation::None => return atom.color,
+            ColorAnimation::Shimmer { speed, strength } => {
+                let wave = pos.x as f32 * 0.35 + pos.y as f32 * 0.2 + hash(pos, 0) as f32 * 2.;
+                strength * (self.time * speed + wave).sin()
+            }
+            ColorAnimation::Flicker { speed, strength } => {
+                strength * (hash(pos, (self.time * speed) as u32) as f32 * 2. - 1.)
+            }
+            ColorAnimation::Pulse { speed, strength } => {
+                let movement = (atom.speed.0.unsigned_abs() + atom.speed.1.unsigned_abs()) as f32
+                    / ATOM_TERM_VEL as f32;
+                let pulse = (self.time * speed + hash(pos, 0) as f32 * 6.).sin() * 0.5 + 0.5;
+                strength * pulse * (0.3 + movement.min(1.) + atom.moving as u8 as f32 * 0.3)
+            }
+        };
+
+        let [r, g, b, a] = atom.color;
+        let shade = |c: u8| (c as f32 * (1. + brightness)).clamp(0., 255.) as u8;
+        [shade(r), shade(g), shade(b), a]
+    }
+}
+
+/// Finds the animated atoms of the changed chunks, and picks which tiles are uploaded this frame
+pub fn update_animated_rects(
+    mut animated: ResMut<AnimatedRects>,
+    chunk_manager: Res<ChunkManager>,
+    dirty_rects: Res<DirtyRects>,
+    materials: (Res<Assets<Materials>>, Res<MaterialsHandle>),
+    time: Res<Time>,
+) {
+    puffin::profile_function!();
+
+    let materials = materials.0.get(&materials.1 .0).unwrap();
+    let animated = &mut *animated;
+
+    animated.animations = materials
+        .0
+        .iter()
+        .map(|material| material.animation)
+        .collect();
+    animated.time = time.elapsed_secs();
+    animated.frame = animated.frame.wrapping_add(1);
+    animated.rects.clear();
+
+    animated
+        .bounds
+        .retain(|index, _| chunk_manager.chunks.contains_key(index));
+
+    for (index, chunk) in &chunk_manager.chunks {
+        if animated.bounds.contains_key(index) && !dirty_rects.render.contains_key(index) {
+            continue;
+        }
+
+        let tiles = CHUNK_LENGHT / COLOR_ANIMATION_TILE;
+        let mut bounds: Vec<Option<URect>> = vec![None; tiles * tiles];
+        for (i, atom) in chunk.atoms.iter().enumerate() {
+            if animated.animations[atom.id as usize] == ColorAnimation::None {
+                continue;
+            }
+
+            let (x, y) = (i % CHUNK_LENGHT, i / CHUNK_LENGHT);
+            let tile = &mut bounds[y / COLOR_ANIMATION_TILE * tiles + x / COLOR_ANIMATION_TILE];
+            let pos = uvec2(x as u32, y as u32);
+            *tile = Some(match *tile {
+                Some(rect) => rect.union_point(pos),
+                None => URect::from_corners(pos, pos),
+            });
+        }
+        animated.bounds.insert(*index, bounds);
+    }
+
+    // Neighbour tiles take different turns, so the uploads are spread over the frames
+    let frame = animated.frame.rem_euclid(COLOR_ANIMATION_INTERVAL);
+    for (index, bounds) in &animated.bounds {
+        let rects: Vec<URect> = bounds
+            .iter()
+            .enumerate()
+            .filter(|(tile, _)| {
+                (index.x + index.y + *tile as i32).rem_euclid(COLOR_ANIMATION_INTERVAL) == frame
+            })
+            .filter_map(|(_, rect)| *rect)
+            .collect();
+        if !rects.is_empty() {
+            animated.rects.insert(*index, rects);
+        }
+    }
+}
+
+pub struct AtomColorsPlugin;
+impl Plugin for AtomColorsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AnimatedRects>().add_systems(
+            PostUpdate,
+            update_animated_rects.run_if(in_state(GameState::Game)),
+        );
+    }
+}
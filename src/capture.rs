@@ -0,0 +1,226 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::prelude::*;
+
+/// Color of the actors on the captures, the player is drawn white
+const ACTOR_COLOR: [u8; 4] = [200, 60, 60, 255];
+
+/// Dumps a capture of the loaded world every `interval` simulation ticks
+#[derive(Resource, Default)]
+pub struct TimeLapse {
+    pub interval: u32,
+    /// Folder of the current time-lapse, none when it's stopped
+    dir: Option<String>,
+    tick: u32,
+    frame: u32,
+}
+
+impl TimeLapse {
+    pub fn new(interval: u32) -> Self {
+        Self {
+            interval,
+            ..Default::default()
+        }
+    }
+
+    pub fn start(&mut self) {
+        let dir = format!("captures/timelapse_{}", unix_secs());
+        std::fs::create_dir_all(&dir).unwrap();
+        info!(
+            "Recording time-lapse to {dir}, one frame every {} ticks",
+            self.interval
+        );
+
+        self.dir = Some(dir);
+        self.tick = 0;
+        self.frame = 0;
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(dir) = self.dir.take() {
+            info!("Saved {} time-lapse frames to {dir}", self.frame);
+        }
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn blend(under: [u8; 4], over: [u8; 4]) -> [u8; 4] {
+    let a = over[3] as u32;
+    let mix = |u: u8, o: u8| ((o as u32 * a + u as u32 * (255 - a)) / 255) as u8;
+    [
+        mix(under[0], over[0]),
+        mix(under[1], over[1]),
+        mix(under[2], over[2]),
+        under[3].max(over[3]),
+    ]
+}
+
+/// Composites the loaded chunks, rigidbodies and actors into RGBA pixels at one pixel per atom.
+/// Built from the atoms on the CPU, so it doesn't depend on the camera or the GPU.
+pub fn capture_world(
+    chunk_manager: &ChunkManager,
+    rigidbodies: &[(&Transform, &Rigidbody)],
+    actors: &[(&Actor, bool)],
+) -> (u32, u32, Vec<u8>) {
+    let (width, height) = (
+        LOAD_WIDTH as usize * CHUNK_LENGHT,
+        LOAD_HEIGHT as usize * CHUNK_LENGHT,
+    );
+    let origin = chunk_manager.pos * CHUNK_LENGHT as i32;
+    let mut data = vec![0; width * height * 4];
+
+    let mut draw = |pos: IVec2, color: [u8; 4]| {
+        let pos = pos - origin;
+        if pos.x < 0 || pos.y < 0 || pos.x >= width as i32 || pos.y >= height as i32 {
+            return;
+        }
+        let i = (pos.y as usize * width + pos.x as usize) * 4;
+        let under = [data[i], data[i + 1], data[i + 2], data[i + 3]];
+        data[i..i + 4].copy_from_slice(&blend(under, color));
+    };
+
+    for (index, chunk) in &chunk_manager.chunks {
+        let chunk_origin = *index * CHUNK_LENGHT as i32;
+        for i in 0..CHUNK_LEN {
+            let pos = chunk_origin + ivec2((i % CHUNK_LENGHT) as i32, (i / CHUNK_LENGHT) as i32);
+
+            let wall = chunk.background[i];
+            if !wall.is_void() {
+                let [r, g, b, a] = wall.color;
+                draw(pos, [r / 5 * 2, g / 5 * 2, b / 5 * 2, a]);
+            }
+
+            let atom = chunk.atoms[i];
+            if !atom.is_void() && !atom.is_object() {
+                draw(pos, atom.color);
+            }
+        }
+    }
+
+    // Same placement as when the rigidbodies fill the chunks
+    for (transform, rigidbody) in rigidbodies {
+        let (width, height) = (rigidbody.width as usize, rigidbody.height as usize);
+        let angle = -transform.rotation.to_euler(EulerRot::XYZ).2;
+        let mut top_left = transform.translation.xy();
+        top_left.y *= -1.;
+
+        for y in 0..height {
+            for x in 0..width {
+                let atom = rigidbody.atoms[y * width + x];
+                if atom.is_solid() {
+                    let pos = top_left + vec2(x as f32, y as f32).rotate(Vec2::from_angle(angle));
+                    draw(pos.round().as_ivec2(), atom.color);
+                }
+            }
+        }
+    }
+
+    for (actor, is_player) in actors {
+        let color = if *is_player { [255; 4] } else { ACTOR_COLOR };
+        for y in 0..actor.height as i32 {
+            for x in 0..actor.width as i32 {
+                draw(actor.pos + ivec2(x, y), color);
+            }
+        }
+    }
+
+    (width as u32, height as u32, data)
+}
+
+/// Saves the capture on the io pool, so the game doesn't stop while it's encoded
+fn save_capture(path: String, capture: (u32, u32, Vec<u8>)) {
+    IoTaskPool::get()
+        .spawn(async move {
+            let (width, height, data) = capture;
+            save_png(&path, width, height, data);
+        })
+        .detach();
+}
+
+pub fn screenshot(
+    inputs: Res<Inputs>,
+    chunk_manager: Res<ChunkManager>,
+    rigidbodies: Query<(&Transform, &Rigidbody)>,
+    actors: Query<(&Actor, Has<Player>)>,
+    mut time_lapse: ResMut<TimeLapse>,
+) {
+    if inputs.time_lapse_toggle {
+        if time_lapse.dir.is_some() {
+            time_lapse.stop();
+        } else {
+            time_lapse.start();
+        }
+    }
+
+    if !inputs.screenshot {
+        return;
+    }
+
+    std::fs::create_dir_all("captures").unwrap();
+    let path = format!("captures/world_{}.png", unix_secs());
+    let capture = capture_world(
+        &chunk_manager,
+        &rigidbodies.iter().collect::<Vec<_>>(),
+        &actors.iter().collect::<Vec<_>>(),
+    );
+    info!("Saving {path}");
+    save_capture(path, capture);
+}
+
+/// Counts the simulation ticks, saving a time-lapse frame every interval
+pub fn time_lapse_capture(
+    chunk_manager: Res<ChunkManager>,
+    rigidbodies: Query<(&Transform, &Rigidbody)>,
+    actors: Query<(&Actor, Has<Player>)>,
+    mut time_lapse: ResMut<TimeLapse>,
+) {
+    let Some(dir) = time_lapse.dir.clone() else {
+        return;
+    };
+
+    time_lapse.tick += 1;
+    if time_lapse.tick < time_lapse.interval.max(1) {
+        return;
+    }
+    time_lapse.tick = 0;
+    time_lapse.frame += 1;
+
+    let capture = capture_world(
+        &chunk_manager,
+        &rigidbodies.iter().collect::<Vec<_>>(),
+        &actors.iter().collect::<Vec<_>>(),
+    );
+    save_capture(format!("{dir}/{:05}.png", time_lapse.frame), capture);
+}
+
+/// Starts the time-lapse with the game if it was asked for with `--timelapse <ticks>`
+fn time_lapse_setup(mut time_lapse: ResMut<TimeLapse>) {
+    let args: Vec<_> = env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--timelapse") {
+        if let Some(interval) = args.get(i + 1).and_then(|arg| arg.parse().ok()) {
+            time_lapse.interval = interval;
+        }
+        time_lapse.start();
+    }
+}
+
+pub struct CapturePlugin;
+impl Plugin for CapturePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TimeLapse::new(TIME_LAPSE_TICKS))
+            .add_systems(OnEnter(GameState::Game), time_lapse_setup)
+            .add_systems(Update, screenshot.run_if(in_state(GameState::Game)))
+            .add_systems(
+                FixedUpdate,
+                time_lapse_capture
+                    .after(chunk_manager_update)
+                    .run_if(in_state(GameState::Game)),
+            );
+    }
+}
@@ -1,38 +1,50 @@
 use bevy::render::{render_asset::RenderAssetUsages, render_resource::*};
-use noise::*;
 use std::collections::HashSet;
 
-use crate::{prelude::*, rigidbody};
+use crate::prelude::*;
 
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Chunk {
     #[serde(with = "BigArray")]
     pub atoms: [Atom; CHUNK_LEN],
+    /// Walls behind the atoms, not simulated.
+    /// Saved on their own file, so worlds saved without them still load.
+    #[serde(skip, default = "empty_background")]
+    pub background: [Atom; CHUNK_LEN],
 
     #[serde(skip)]
     pub texture: Handle<Image>,
     #[serde(skip)]
+    pub background_texture: Handle<Image>,
+    #[serde(skip)]
     pub entity: Option<Entity>,
+    /// Entities from structures, spawned once a session when the chunk is added.
+    /// Saved on their own file, so they come back with the world until they are collected.
+    #[serde(skip)]
+    pub spawns: Vec<StructureSpawn>,
+    /// Indices of the spawns that were collected or destroyed
+    #[serde(skip)]
+    pub collected: Vec<usize>,
+}
+
+fn empty_background() -> [Atom; CHUNK_LEN] {
+    [Atom::default(); CHUNK_LEN]
 }
 
 impl Default for Chunk {
     fn default() -> Self {
         Self {
             atoms: [Atom::default(); CHUNK_LEN],
+            background: [Atom::default(); CHUNK_LEN],
             texture: Handle::default(),
+            background_texture: Handle::default(),
             entity: None,
+            spawns: vec![],
+            collected: vec![],
         }
     }
 }
 
-#[derive(Clone, Copy)]
-struct YCoord;
-impl NoiseFn<f64, 2> for YCoord {
-    fn get(&self, point: [f64; 2]) -> f64 {
-        point[1]
-    }
-}
-
 impl Chunk {
     pub fn new(
         texture: Handle<Image>,
@@ -40,78 +52,26 @@ impl Chunk {
         materials: &Materials,
         generator: &Generator,
     ) -> Chunk {
-        let mut atoms = [Atom::default(); CHUNK_LEN];
-
-        let air_limit = -1000.;
-
-        let (scale, cave) = (generator.1, generator.0.clone());
-
-        let cave: Multiply<f64, RidgedMulti<SuperSimplex>, Constant, 2> =
-            Multiply::new(cave, Constant::new(0.7));
-
-        let ground_noise = BasicMulti::<SuperSimplex>::new(generator.2);
-        let ground = Add::new(
-            ScalePoint::new(ground_noise)
-                .set_y_scale(0.3)
-                .set_x_scale(0.3),
-            YCoord,
-        );
+        let mut chunk = GenChunk::new(index, materials);
+        for stage in &generator.stages {
+            stage.run(&mut chunk, generator);
+        }
 
-        let cave_start = 0.5;
-        let falloff = 0.3;
-        let control = Add::new(ground.clone(), Constant::new(-cave_start));
-        let generator = Select::new(cave, ground.clone(), control.clone())
-            .set_bounds(air_limit, cave_start) // For y values within this range, use the ground noise.
-            .set_falloff(falloff);
-
-        for x in 0..CHUNK_LENGHT {
-            for y in 0..CHUNK_LENGHT {
-                let pos = [
-                    (index.x as f64 * CHUNK_LENGHT as f64 + x as f64) / scale,
-                    (index.y as f64 * CHUNK_LENGHT as f64 + y as f64) / scale,
-                ];
-                let noise = generator.get(pos);
-                let i = y * CHUNK_LENGHT + x;
-
-                /*atoms[i].color = [
-                    ((noise as f32 + 1.) / 2. * 100.) as u8,
-                    ((noise as f32 + 1.) / 2. * 100.) as u8,
-                    ((noise as f32 + 1.) / 2. * 100.) as u8,
-                    255,
-                ];*/
-                let id = if (air_limit..(cave_start - falloff)).contains(&control.get(pos)) {
-                    // Ground layer
-                    match (noise + 1.) / 2. {
-                        ..0.2 => continue,
-                        0.2..0.21 => 6,
-                        0.21..0.5 => 7,
-                        0.5..0.75 => 10,
-                        0.75.. => 4,
-                        _ => panic!("{noise}"),
-                    }
-                } else {
-                    // Cave layer
-                    match (noise + 1.) / 2. {
-                        ..0.2 => continue,
-                        0.2.. => 8,
-                        _ => panic!("{noise}"),
-                    }
-                };
-
-                atoms[i] = Atom::new(id, materials);
-                /*atoms[i].color = [
-                    ((noise as f32 + 1.) / 2. * 100.) as u8,
-                    ((noise as f32 + 1.) / 2. * 100.) as u8,
-                    ((noise as f32 + 1.) / 2. * 100.) as u8,
-                    255,
-                ];*/
+        let mut background = [Atom::default(); CHUNK_LEN];
+        for (atom, id) in background.iter_mut().zip(chunk.background) {
+            if id != 0 {
+                *atom = Atom::new(id, materials);
             }
         }
 
         Chunk {
-            atoms,
+            atoms: chunk.atoms,
+            background,
             texture,
+            background_texture: Handle::default(),
             entity: None,
+            spawns: chunk.spawns,
+            collected: vec![],
         }
     }
 
@@ -137,6 +97,28 @@ impl Chunk {
         }
     }
 
+    /// Image of the background, darker than the atoms
+    pub fn background_image(&self) -> Image {
+        let mut image = Self::new_image();
+        image.asset_usage = RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD;
+        self.update_background(
+            &mut image,
+            URect::new(0, 0, CHUNK_LENGHT as u32 - 1, CHUNK_LENGHT as u32 - 1),
+        );
+        image
+    }
+
+    pub fn update_background(&self, image: &mut Image, rect: URect) {
+        for y in rect.min.y..=rect.max.y {
+            for x in rect.min.x..=rect.max.x {
+                let i = y as usize * CHUNK_LENGHT + x as usize;
+                let [r, g, b, a] = self.background[i].color;
+                let color = [r / 5 * 2, g / 5 * 2, b / 5 * 2, a];
+                image.data[i * 4..i * 4 + 4].copy_from_slice(&color);
+            }
+        }
+    }
+
     pub fn update_all(&self, image: &mut Image) {
         let positions: HashSet<IVec2> = (0..CHUNK_LENGHT)
             .flat_map(|y| (0..CHUNK_LENGHT).map(move |x| IVec2::new(x as i32, y as i32)))
@@ -145,8 +127,36 @@ impl Chunk {
         self.update_image_positions(image, &positions)
     }
 
-    pub fn get_collider(&self) -> Option<Collider> {
-        rigidbody::get_collider(&self.get_values(), CHUNK_LENGHT as u32, CHUNK_LENGHT as u32)
+    /// Removes the object atoms, like the ones left by resting rigidbodies, before saving
+    pub fn clear_objects(&mut self) {
+        for atom in self.atoms.iter_mut().filter(|atom| atom.is_object()) {
+            *atom = Atom::default();
+        }
+    }
+
+    /// Which atoms are solid, a bit for each atom
+    pub fn solid_mask(&self) -> Vec<u64> {
+        self.atoms
+            .chunks(CHUNK_LENGHT)
+            .map(|atoms| {
+                atoms
+                    .iter()
+                    .enumerate()
+                    .fold(0, |mask, (i, atom)| mask | ((atom.is_solid() as u64) << i))
+            })
+            .collect()
+    }
+
+    /// Which atoms actors can't go through, a bit for each atom and a `u64` for each row
+    pub fn blocking_mask(&self) -> Vec<u64> {
+        self.atoms
+            .chunks(CHUNK_LENGHT)
+            .map(|atoms| {
+                atoms.iter().enumerate().fold(0, |mask, (i, atom)| {
+                    mask | (((atom.is_solid() || atom.is_powder()) as u64) << i)
+                })
+            })
+            .collect()
     }
 
     pub fn get_values(&self) -> Vec<f64> {
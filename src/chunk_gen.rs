@@ -0,0 +1,224 @@
+use crate::prelude::*;
+
+/// How many chunks outside of the loaded area are generated ahead of the player
+pub const PREGEN_CHUNKS: i32 = 3;
+/// How many frames of the player velocity are used to predict where it's going
+pub const PREGEN_LOOK_AHEAD: f32 = 30.;
+
+/// Generates new chunks on the async compute pool, closest to where the player is going first.
+/// Chunks in the loaded area that are still generating are missing from the manager,
+/// so they aren't simulated and the world treats them like it's border.
+#[derive(Resource, Default)]
+pub struct GenQueue {
+    /// Shared with the generation tasks
+    context: Option<Arc<(Generator, Materials)>>,
+    /// Chunks waiting for a task
+    queued: HashSet<IVec2>,
+    tasks: HashMap<IVec2, Task<Chunk>>,
+    /// Generated chunks outside of the loaded area, waiting to be loaded
+    ready: HashMap<IVec2, Chunk>,
+}
+
+impl GenQueue {
+    pub fn new(generator: Generator, materials: Materials) -> Self {
+        Self {
+            context: Some(Arc::new((generator, materials))),
+            ..Default::default()
+        }
+    }
+
+    /// Queues the chunk to be generated, if it isn't already
+    pub fn request(&mut self, index: IVec2) {
+        if !self.tasks.contains_key(&index) && !self.ready.contains_key(&index) {
+            self.queued.insert(index);
+        }
+    }
+
+    /// Takes the chunk if it was already generated
+    pub fn take(&mut self, index: IVec2) -> Option<Chunk> {
+        self.ready.remove(&index)
+    }
+
+    /// Name of the most present biome at a global atom position
+    pub fn biome_at(&self, pos: IVec2) -> Option<&str> {
+        let (generator, _) = &**self.context.as_ref()?;
+        let (biome, _) = generator
+            .biome_weights(pos)
+            .into_iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+        Some(&generator.config.biomes[biome].name)
+    }
+}
+
+fn loaded_rect(manager_pos: IVec2) -> IRect {
+    IRect::new(
+        manager_pos.x,
+        manager_pos.y,
+        manager_pos.x + LOAD_WIDTH - 1,
+        manager_pos.y + LOAD_HEIGHT - 1,
+    )
+}
+
+/// Queues the chunks just outside the loaded area, on the side the player is going
+pub fn queue_pregen(
+    chunk_manager: Res<ChunkManager>,
+    mut gen_queue: ResMut<GenQueue>,
+    player: Query<&Actor, With<Player>>,
+) {
+    let Ok(actor) = player.get_single() else {
+        return;
+    };
+
+    let loaded = loaded_rect(chunk_manager.pos);
+    let dir = |vel: f32| {
+        if vel.abs() > 0.5 {
+            vel.signum() as i32
+        } else {
+            0
+        }
+    };
+    let dir = ivec2(dir(actor.vel.x), dir(actor.vel.y));
+
+    if dir.x != 0 {
+        let start = if dir.x > 0 {
+            loaded.max.x + 1
+        } else {
+            loaded.min.x - PREGEN_CHUNKS
+        };
+        for x in start..start + PREGEN_CHUNKS {
+            for y in loaded.min.y..=loaded.max.y {
+                gen_queue.request(ivec2(x, y));
+            }
+        }
+    }
+
+    if dir.y != 0 {
+        let start = if dir.y > 0 {
+            loaded.max.y + 1
+        } else {
+            loaded.min.y - PREGEN_CHUNKS
+        };
+        for y in start..start + PREGEN_CHUNKS {
+            for x in loaded.min.x..=loaded.max.x {
+                gen_queue.request(ivec2(x, y));
+            }
+        }
+    }
+}
+
+/// Starts generating the queued chunks, by the distance to where the player is going
+pub fn start_gen_tasks(
+    chunk_manager: Res<ChunkManager>,
+    mut gen_queue: ResMut<GenQueue>,
+    player: Query<&Actor, With<Player>>,
+) {
+    let Some(context) = gen_queue.context.clone() else {
+        return;
+    };
+
+    // Forgets chunks too far to be loaded soon
+    let keep = loaded_rect(chunk_manager.pos).inflate(PREGEN_CHUNKS);
+    gen_queue.queued.retain(|index| keep.contains(*index));
+    gen_queue.ready.retain(|index, _| keep.contains(*index));
+
+    let pool = AsyncComputeTaskPool::get();
+    let free = pool
+        .thread_num()
+        .max(1)
+        .saturating_sub(gen_queue.tasks.len());
+    if free == 0 || gen_queue.queued.is_empty() {
+        return;
+    }
+
+    let target = match player.get_single() {
+        Ok(actor) => actor.pos.as_vec2() + actor.vel * PREGEN_LOOK_AHEAD,
+        Err(_) => ((chunk_manager.pos + ivec2(LOAD_WIDTH, LOAD_HEIGHT) / 2) * CHUNK_LENGHT as i32)
+            .as_vec2(),
+    };
+
+    let mut queued: Vec<IVec2> = gen_queue.queued.iter().copied().collect();
+    queued.sort_by(|a, b| {
+        let distance = |index: &IVec2| {
+            let center = (*index * CHUNK_LENGHT as i32).as_vec2() + CHUNK_LENGHT as f32 / 2.;
+            center.distance_squared(target)
+        };
+        distance(a).total_cmp(&distance(b))
+    });
+
+    for index in queued.into_iter().take(free) {
+        gen_queue.queued.remove(&index);
+
+        let context = context.clone();
+        let task = pool.spawn(async move {
+            let (generator, materials) = &*context;
+            Chunk::new(Handle::default(), index, materials, generator)
+        });
+        gen_queue.tasks.insert(index, task);
+    }
+}
+
+/// Adds the finished chunks to the manager, or keeps them if they aren't loaded yet
+pub fn poll_gen_tasks(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    mut gen_queue: ResMut<GenQueue>,
+    chunk_textures: Query<Entity, With<ChunksParent>>,
+) {
+    let finished: Vec<IVec2> = gen_queue
+        .tasks
+        .iter()
+        .filter(|(_, task)| task.is_finished())
+        .map(|(index, _)| *index)
+        .collect();
+
+    if finished.is_empty() {
+        return;
+    }
+
+    let Ok(chunk_textures) = chunk_textures.get_single() else {
+        return;
+    };
+
+    let loaded = loaded_rect(chunk_manager.pos);
+    let mut images_vec = vec![];
+    for index in finished {
+        let task = gen_queue.tasks.remove(&index).unwrap();
+        let chunk = block_on(task);
+
+        if loaded.contains(index) && !chunk_manager.chunks.contains_key(&index) {
+            images_vec.push(add_chunk(
+                &mut commands,
+                &mut images,
+                &mut chunk_manager,
+                chunk,
+                index,
+            ));
+        } else {
+            gen_queue.ready.insert(index, chunk);
+        }
+    }
+
+    commands
+        .entity(chunk_textures)
+        .insert_children(0, &images_vec);
+}
+
+pub struct ChunkGenPlugin;
+impl Plugin for ChunkGenPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GenQueue>()
+            .init_resource::<GenPipeline>()
+            .add_systems(
+                Update,
+                (
+                    queue_pregen.after(update_manager_pos),
+                    start_gen_tasks.after(queue_pregen),
+                    poll_gen_tasks.after(update_manager_pos),
+                )
+                    .run_if(in_state(GameState::Game)),
+            );
+    }
+}
@@ -239,13 +239,14 @@ pub fn update_chunk_groups<'a>(
     senders: (
         &'a Sender<DeferredDirtyRectUpdate>,
         &'a Sender<DeferredDirtyRectUpdate>,
+        &'a Sender<Particle>,
     ),
     update: (u8, &'a Materials),
     scope: &Scope<'a, '_, ()>,
 ) {
     puffin::profile_function!();
 
-    let (dirty_update_rect_send, dirty_render_rect_send) = senders;
+    let (dirty_update_rect_send, dirty_render_rect_send, splash_send) = senders;
     let (dt, materials) = update;
 
     for chunk_pos in dirty_rects.keys() {
@@ -386,6 +387,7 @@ pub fn update_chunk_groups<'a>(
                     group: &mut chunk_group,
                     dirty_update_rect_send,
                     dirty_render_rect_send,
+                    splash_send,
                     materials,
                 },
                 dt,
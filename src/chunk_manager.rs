@@ -9,8 +9,6 @@ use bevy::sprite::Anchor;
 use itertools::Itertools;
 use smallvec::SmallVec;
 
-use noise::{MultiFractal, RidgedMulti, SuperSimplex};
-
 use crate::prelude::*;
 
 /// Updates and do the chunks logic
@@ -19,6 +17,29 @@ pub struct ChunkManager {
     pub chunks: HashMap<IVec2, Chunk>,
     pub pos: IVec2,
     pub dt: u8,
+    pub spawned: SpawnedStructures,
+}
+
+/// Structure entities spawned from the chunks this session
+#[derive(Default)]
+pub struct SpawnedStructures {
+    /// Chunks with their spawns already in the world
+    pub chunks: HashSet<IVec2>,
+    /// Spawned pickups and rigidbodies, with their chunk and spawn index
+    pub entities: HashMap<Entity, (IVec2, usize)>,
+    /// Spawns collected from chunks that weren't loaded, to be added to the saved chunks
+    pub collected: HashMap<IVec2, Vec<usize>>,
+}
+
+impl SpawnedStructures {
+    /// Adds the spawns collected from unloaded chunks to the saved chunks
+    pub fn save_collected(&mut self, file_chunks: &mut HashMap<IVec2, Chunk>) {
+        for (index, collected) in self.collected.drain() {
+            if let Some(chunk) = file_chunks.get_mut(&index) {
+                chunk.collected.extend(collected);
+            }
+        }
+    }
 }
 
 //If true the direction is 1, if false the direction is -1
@@ -45,6 +66,14 @@ impl ChunkManager {
         }
     }
 
+    pub fn get_mut_background(&mut self, pos: ChunkPos) -> Option<&mut Atom> {
+        if let Some(chunk) = self.chunks.get_mut(&pos.chunk) {
+            chunk.background.get_mut(pos.atom.d1())
+        } else {
+            None
+        }
+    }
+
     //Still needs to save file chunks to file after this function is called
     pub fn move_manager(
         &mut self,
@@ -53,10 +82,9 @@ impl ChunkManager {
         chunk_textures: &Entity,
         image_entities: &Query<(&Parent, Entity, &Sprite)>,
         file_chunks: &mut HashMap<IVec2, Chunk>,
-        dir_materials: (MoveDir, &Materials, &Generator),
+        move_dir: MoveDir,
+        gen_queue: &mut GenQueue,
     ) {
-        let (move_dir, materials, generator) = dir_materials;
-
         let (for_load, vec_load, dir, y) = match move_dir {
             MoveDir::X(dir) => {
                 self.pos.x += dir;
@@ -79,14 +107,18 @@ impl ChunkManager {
                 }
                 let pos = self.pos + to_add;
 
-                let changed_chunk = self.chunks.remove(&pos).unwrap();
-                to_remove.push(changed_chunk.texture.clone());
-                images.remove(&changed_chunk.texture);
-
-                if let Some(chunk) = file_chunks.get_mut(&pos) {
-                    *chunk = changed_chunk;
-                } else {
-                    file_chunks.insert(pos, changed_chunk);
+                //If still generating, it's kept by the queue until loaded again
+                if let Some(mut changed_chunk) = self.chunks.remove(&pos) {
+                    changed_chunk.clear_objects();
+                    to_remove.push(changed_chunk.texture.clone());
+                    images.remove(&changed_chunk.texture);
+                    images.remove(&changed_chunk.background_texture);
+
+                    if let Some(chunk) = file_chunks.get_mut(&pos) {
+                        *chunk = changed_chunk;
+                    } else {
+                        file_chunks.insert(pos, changed_chunk);
+                    }
                 }
             }
 
@@ -100,8 +132,11 @@ impl ChunkManager {
 
                 let chunk = if let Some(file_chunk) = file_chunks.get(&pos) {
                     file_chunk.clone()
+                } else if let Some(chunk) = gen_queue.take(pos) {
+                    chunk
                 } else {
-                    Chunk::new(Handle::default(), pos, materials, generator)
+                    gen_queue.request(pos);
+                    continue;
                 };
 
                 images_vec.push(add_chunk(commands, images, self, chunk, pos));
@@ -110,7 +145,7 @@ impl ChunkManager {
 
         for (parent, ent, handle) in image_entities.iter() {
             if parent.get() == *chunk_textures && to_remove.contains(&handle.image) {
-                commands.get_entity(ent).unwrap().despawn();
+                commands.get_entity(ent).unwrap().despawn_recursive();
             }
         }
         let mut chunk_textures = commands.get_entity(*chunk_textures).unwrap();
@@ -120,23 +155,78 @@ impl ChunkManager {
 
 impl Drop for ChunkManager {
     fn drop(&mut self) {
-        let file = File::open("assets/world/world").unwrap();
-        let mut buffered = BufReader::new(file);
-        let mut file_chunks: HashMap<IVec2, Chunk> =
-            bincode::deserialize_from(&mut buffered).unwrap();
+        let mut file_chunks = load_chunks();
+        self.spawned.save_collected(&mut file_chunks);
 
         for (pos, chunk) in &self.chunks {
-            if let Some(file_chunk) = file_chunks.get_mut(pos) {
-                *file_chunk = chunk.clone();
-            } else {
-                file_chunks.insert(*pos, chunk.clone());
+            let mut chunk = chunk.clone();
+            chunk.clear_objects();
+            file_chunks.insert(*pos, chunk);
+        }
+
+        save_chunks(&file_chunks);
+    }
+}
+
+/// Loads the saved chunks, with their walls and structure spawns.
+/// Chunks saved before walls or spawns existed have none of them.
+pub fn load_chunks() -> HashMap<IVec2, Chunk> {
+    let file = File::open("assets/world/world").unwrap();
+    let mut buffered = BufReader::new(file);
+    let mut chunks: HashMap<IVec2, Chunk> = bincode::deserialize_from(&mut buffered).unwrap();
+
+    if let Ok(file) = File::open("assets/world/walls") {
+        let mut buffered = BufReader::new(file);
+        let walls: HashMap<IVec2, Vec<Atom>> = bincode::deserialize_from(&mut buffered).unwrap();
+        for (index, walls) in walls {
+            if let Some(chunk) = chunks.get_mut(&index) {
+                chunk.background.copy_from_slice(&walls);
             }
         }
+    }
 
-        let file = File::create("assets/world/world").unwrap();
-        let mut buffered = BufWriter::new(file);
-        bincode::serialize_into(&mut buffered, &file_chunks).unwrap();
+    if let Ok(file) = File::open("assets/world/spawns") {
+        let mut buffered = BufReader::new(file);
+        let spawns: HashMap<IVec2, (Vec<StructureSpawn>, Vec<usize>)> =
+            bincode::deserialize_from(&mut buffered).unwrap();
+        for (index, (spawns, collected)) in spawns {
+            if let Some(chunk) = chunks.get_mut(&index) {
+                chunk.spawns = spawns;
+                chunk.collected = collected;
+            }
+        }
     }
+
+    chunks
+}
+
+/// Saves the chunks, and their walls and structure spawns on separate files
+pub fn save_chunks(chunks: &HashMap<IVec2, Chunk>) {
+    let file = File::create("assets/world/world").unwrap();
+    let mut buffered = BufWriter::new(file);
+    bincode::serialize_into(&mut buffered, chunks).unwrap();
+
+    let walls: HashMap<IVec2, &[Atom]> = chunks
+        .iter()
+        .map(|(index, chunk)| (*index, chunk.background.as_slice()))
+        .collect();
+    let file = File::create("assets/world/walls").unwrap();
+    let mut buffered = BufWriter::new(file);
+    bincode::serialize_into(&mut buffered, &walls).unwrap();
+
+    let spawns: HashMap<IVec2, (&[StructureSpawn], &[usize])> = chunks
+        .iter()
+        .filter(|(_, chunk)| !chunk.spawns.is_empty())
+        .map(|(index, chunk)| {
+            (
+                *index,
+                (chunk.spawns.as_slice(), chunk.collected.as_slice()),
+            )
+        })
+        .collect();
+    let file = File::create("assets/world/spawns").unwrap();
+    let mut buffered = BufWriter::new(file);
+    bincode::serialize_into(&mut buffered, &spawns).unwrap();
 }
 
 impl std::ops::Index<ChunkPos> for ChunkManager {
@@ -161,6 +251,8 @@ pub struct DirtyRects {
     pub new: HashMap<IVec2, URect>,
     /// The dirty render rects
     pub render: HashMap<IVec2, URect>,
+    /// The changed background rects
+    pub background: HashMap<IVec2, URect>,
 }
 
 impl DirtyRects {
@@ -172,57 +264,45 @@ impl DirtyRects {
 #[derive(Component)]
 pub struct ChunksParent;
 
-#[derive(Resource, Default)]
-pub struct Generator(pub RidgedMulti<SuperSimplex>, pub f64, pub u32);
-
 pub fn manager_setup(
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
     mut chunk_manager: ResMut<ChunkManager>,
     materials: (Res<Assets<Materials>>, ResMut<MaterialsHandle>),
-    mut generator: ResMut<Generator>,
+    mut gen_queue: ResMut<GenQueue>,
+    gen_assets: (Res<StructureAssets>, Res<GenPipeline>),
 ) {
+    let (structures, pipeline) = gen_assets;
     let (width, height) = (LOAD_WIDTH, LOAD_HEIGHT);
 
     let mut images_vec = vec![];
     chunk_manager.pos = ivec2(-16, -16);
 
     let file_chunks: HashMap<IVec2, Chunk>;
-    if let Ok(file) = File::open("assets/world/world") {
-        let mut buffered = BufReader::new(file);
-        file_chunks = bincode::deserialize_from(&mut buffered).unwrap();
+    if File::open("assets/world/world").is_ok() {
+        file_chunks = load_chunks();
     } else {
         file_chunks = HashMap::new();
-        let file = File::create("assets/world/world").unwrap();
-        let mut buffered = BufWriter::new(file);
-        bincode::serialize_into(&mut buffered, &file_chunks).unwrap();
+        save_chunks(&file_chunks);
     }
 
-    let bytes = std::fs::read("assets/gen.ron").unwrap();
-    let gen_config = ron::de::from_bytes::<[f64; 6]>(&bytes).unwrap();
-
-    generator.0 = RidgedMulti::<SuperSimplex>::new(generator.2)
-        .set_octaves(gen_config[1] as usize)
-        .set_lacunarity(gen_config[2])
-        .set_persistence(gen_config[3])
-        .set_attenuation(gen_config[4]);
-    generator.1 = gen_config[0];
-    generator.2 = gen_config[5] as u32;
+    let mut generator = Generator::load("assets/gen.ron");
+    generator.structures = structures.build(&images);
+    generator.stages = pipeline.stages.clone();
+    let materials = materials.0.get(&materials.1 .0).unwrap();
+    *gen_queue = GenQueue::new(generator, materials.clone());
 
     for (x, y) in (chunk_manager.pos.x..chunk_manager.pos.x + width)
         .cartesian_product(chunk_manager.pos.y..chunk_manager.pos.y + height)
     {
         let index = ivec2(x, y);
-        let chunk;
         if let Some(file_chunk) = file_chunks.get(&index) {
-            chunk = file_chunk.clone();
+            let chunk = file_chunk.clone();
+            let ent = add_chunk(&mut commands, &mut images, &mut chunk_manager, chunk, index);
+            images_vec.push(ent);
         } else {
-            let materials = materials.0.get(&materials.1 .0).unwrap();
-            chunk = Chunk::new(Handle::default(), index, materials, &generator);
+            gen_queue.request(index);
         }
-
-        let ent = add_chunk(&mut commands, &mut images, &mut chunk_manager, chunk, index);
-        images_vec.push(ent);
     }
 
     commands
@@ -235,35 +315,100 @@ pub fn manager_setup(
         .insert_children(0, &images_vec);
 }
 
-pub fn add_colliders(
+/// Chunk colliders with the solid mask they were built from, so they are only rebuilt when it changes
+#[derive(Resource, Default)]
+pub struct ChunkColliders {
+    cache: HashMap<IVec2, (Vec<u64>, Option<Collider>)>,
+    /// Colliders being built on the async compute pool, with the mask they are built from
+    tasks: HashMap<IVec2, (Vec<u64>, Task<Option<Collider>>)>,
+}
+
+/// Adds colliders to the chunks near rigidbodies, and rebuilds them when their solid atoms change
+pub fn update_colliders(
     mut commands: Commands,
     chunk_manager: Res<ChunkManager>,
-    chunks: Query<(Entity, &ChunkComponent), Without<Collider>>,
+    chunks: Query<(Entity, &ChunkComponent, Has<Collider>)>,
     has_collider: Res<HasCollider>,
+    dirty_rects: Res<DirtyRects>,
+    mut colliders: ResMut<ChunkColliders>,
 ) {
     puffin::profile_function!();
 
-    if has_collider.0.is_empty() {
-        return;
-    }
+    let ChunkColliders { cache, tasks } = &mut *colliders;
+    cache.retain(|index, _| chunk_manager.chunks.contains_key(index));
+    tasks.retain(|index, _| chunk_manager.chunks.contains_key(index));
 
-    for (ent, pos) in &chunks {
-        for rect in has_collider.0.iter() {
-            if rect.contains(pos.0) {
-                if let Some(chunk) = chunk_manager.chunks.get(&pos.0) {
-                    let collider = chunk.get_collider();
-
-                    if let Some(collider) = collider {
-                        commands
-                            .entity(ent)
-                            .insert(collider)
-                            .insert(bevy_rapier2d::prelude::RigidBody::Fixed);
-                    }
+    let near_rigidbody = |index: IVec2| has_collider.0.iter().any(|rect| rect.contains(index));
+
+    for (ent, pos, has) in &chunks {
+        // Every changed atom is in the render rects, so unchanged chunks keep their collider
+        if !near_rigidbody(pos.0) || (has && !dirty_rects.render.contains_key(&pos.0)) {
+            continue;
+        }
+        let Some(chunk) = chunk_manager.chunks.get(&pos.0) else {
+            continue;
+        };
+
+        let mask = chunk.solid_mask();
+        if tasks
+            .get(&pos.0)
+            .is_some_and(|(building, _)| *building == mask)
+        {
+            continue;
+        }
+
+        match cache.get(&pos.0) {
+            Some((cached, collider)) if *cached == mask => {
+                // The chunk went back to the cached mask, so a collider being built is outdated
+                tasks.remove(&pos.0);
+                if let (false, Some(collider)) = (has, collider) {
+                    commands
+                        .entity(ent)
+                        .insert(collider.clone())
+                        .insert(RapierRigidbody::Fixed);
                 }
+            }
+            _ => {
+                let values = chunk.get_values();
+                let task = AsyncComputeTaskPool::get().spawn(async move {
+                    get_collider(&values, CHUNK_LENGHT as u32, CHUNK_LENGHT as u32)
+                });
+                tasks.insert(pos.0, (mask, task));
+            }
+        }
+    }
 
-                break;
+    let finished: Vec<IVec2> = tasks
+        .iter()
+        .filter(|(_, (_, task))| task.is_finished())
+        .map(|(index, _)| *index)
+        .collect();
+
+    for index in finished {
+        let (mask, task) = tasks.remove(&index).unwrap();
+        let collider = block_on(task);
+
+        let entity = chunk_manager
+            .chunks
+            .get(&index)
+            .and_then(|chunk| chunk.entity);
+        if let Some(mut entity) = entity
+            .filter(|_| near_rigidbody(index))
+            .and_then(|entity| commands.get_entity(entity))
+        {
+            match &collider {
+                Some(collider) => {
+                    entity
+                        .insert(collider.clone())
+                        .insert(RapierRigidbody::Fixed);
+                }
+                None => {
+                    entity.remove::<(Collider, RapierRigidbody)>();
+                }
             }
         }
+
+        cache.insert(index, (mask, collider));
     }
 }
 
@@ -350,6 +495,7 @@ pub fn update_has_collider(
 pub fn chunk_manager_update(
     mut chunk_manager: ResMut<ChunkManager>,
     mut dirty_rects_resource: ResMut<DirtyRects>,
+    mut particles: ResMut<Particles>,
     materials: (Res<Assets<Materials>>, Res<MaterialsHandle>),
 ) {
     puffin::profile_function!();
@@ -381,6 +527,10 @@ pub fn chunk_manager_update(
         async_channel::unbounded::<DeferredDirtyRectUpdate>();
     let dirty_render_rect_send = &dirty_render_rects_send;
 
+    // Create channel for sending the particles splashed by impacts
+    let (splash_send, splash_recv) = async_channel::unbounded::<Particle>();
+    let splash_send = &splash_send;
+
     // Create a scope in which we handle deferred updates and update chunks.
     compute_pool.scope(|deferred_scope| {
         // Spawn a task on the deferred scope for handling the deferred dirty update rects.
@@ -419,7 +569,7 @@ pub fn chunk_manager_update(
                     (x_toff, y_toff),
                     dirty_rects,
                     manager_pos,
-                    (dirty_update_rect_send, dirty_render_rect_send),
+                    (dirty_update_rect_send, dirty_render_rect_send, splash_send),
                     (dt, materials),
                     scope,
                 );
@@ -433,6 +583,10 @@ pub fn chunk_manager_update(
 
     // Once we are done with our updates, swap the new dirty rects to the current one.
     dirty_rects_resource.swap();
+
+    while let Ok(particle) = splash_recv.try_recv() {
+        particles.spawn(particle);
+    }
 }
 
 pub fn update_chunks(chunks: &mut UpdateChunksType, dt: u8, dirty_rect: &URect) {
@@ -514,6 +668,76 @@ pub fn update_chunks(chunks: &mut UpdateChunksType, dt: u8, dirty_rect: &URect)
 #[derive(Component)]
 pub struct ChunkComponent(pub IVec2);
 
+/// Spawns the structure entities of the chunk that weren't collected
+fn spawn_structures(
+    commands: &mut Commands,
+    spawned: &mut SpawnedStructures,
+    chunk: &Chunk,
+    index: IVec2,
+) {
+    // Collected rigidbodies keep their place, so the joints find the right ones
+    let mut bodies = vec![];
+    for (i, spawn) in chunk.spawns.iter().enumerate() {
+        let collected = chunk.collected.contains(&i);
+        match spawn {
+            StructureSpawn::Rigidbody(name, pos) => {
+                let body =
+                    (!collected).then(|| commands.spawn(RigidbodyHandle(name.clone(), *pos)).id());
+                if let Some(body) = body {
+                    spawned.entities.insert(body, (index, i));
+                }
+                bodies.push(body);
+            }
+            StructureSpawn::Pickup(pickup) => {
+                if !collected {
+                    let ent = commands.spawn((pickup.sprite(), pickup.clone())).id();
+                    spawned.entities.insert(ent, (index, i));
+                }
+            }
+            StructureSpawn::Joint(joint, origin, first_body) => {
+                let body = |i: usize| bodies.get(first_body + i).copied();
+                let other = match joint.to {
+                    StructureJointTo::Body(i, _) => body(i).map(|other| other.map(Some)),
+                    StructureJointTo::Terrain(..) => Some(Some(None)),
+                };
+                let (Some(body), Some(other)) = (body(joint.body), other) else {
+                    error!("Structure joint connects a rigidbody the structure doesn't have");
+                    continue;
+                };
+                // Joints of collected rigidbodies are gone with them
+                let (Some(body), Some(other)) = (body, other) else {
+                    continue;
+                };
+
+                commands.spawn(JointHandle {
+                    joint: *joint,
+                    origin: *origin,
+                    body,
+                    other,
+                });
+            }
+        }
+    }
+}
+
+/// Marks the spawns with despawned entities as collected, so they don't come back
+pub fn collect_spawns(mut chunk_manager: ResMut<ChunkManager>, entities: Query<Entity>) {
+    let chunk_manager = &mut *chunk_manager;
+    let spawned = &mut chunk_manager.spawned;
+    spawned.entities.retain(|ent, &mut (index, spawn)| {
+        if entities.get(*ent).is_ok() {
+            return true;
+        }
+
+        if let Some(chunk) = chunk_manager.chunks.get_mut(&index) {
+            chunk.collected.push(spawn);
+        } else {
+            spawned.collected.entry(index).or_default().push(spawn);
+        }
+        false
+    });
+}
+
 //Still needs to add the return entity to a parent
 pub fn add_chunk(
     commands: &mut Commands,
@@ -527,6 +751,11 @@ pub fn add_chunk(
         (-index.y as f32) * CHUNK_LENGHT as f32,
     );
 
+    // Structure entities stay when the chunk is unloaded, so they are spawned once a session
+    if chunk_manager.spawned.chunks.insert(index) {
+        spawn_structures(commands, &mut chunk_manager.spawned, &chunk, index);
+    }
+
     //Add texture
     chunk.texture = images.add(Chunk::new_image());
     let texture_copy = chunk.texture.clone();
@@ -535,7 +764,9 @@ pub fn add_chunk(
     let image = images.get_mut(&chunk.texture).unwrap();
     chunk.update_all(image);
 
-    //Spawn Image
+    chunk.background_texture = images.add(chunk.background_image());
+
+    //Spawn Image, with the background behind it
     let entity = commands
         .spawn((
             Sprite {
@@ -548,6 +779,14 @@ pub fn add_chunk(
             ChunkComponent(index),
         ))
         .insert(Transform::from_xyz(pos.x, pos.y, 0.))
+        .with_child((
+            Sprite {
+                image: chunk.background_texture.clone(),
+                anchor: Anchor::TopLeft,
+                ..Default::default()
+            },
+            Transform::from_xyz(0., 0., BACKGROUND_LAYER - AUTOMATA_LAYER),
+        ))
         .id();
     chunk.entity = Some(entity);
 
@@ -564,14 +803,11 @@ pub fn update_manager_pos(
         ResMut<SavingTask>,
         ResMut<ChunkManager>,
         ResMut<Assets<Image>>,
-        Res<Generator>,
+        ResMut<GenQueue>,
     ),
     mut task_executor: AsyncTaskRunner<(HashMap<IVec2, Chunk>, IVec2)>,
-    materials: (Res<Assets<Materials>>, Res<MaterialsHandle>),
 ) {
-    let materials = materials.0.get(&materials.1 .0).unwrap();
-
-    let (mut saving_task, mut chunk_manager, mut images, generator) = resources;
+    let (mut saving_task, mut chunk_manager, mut images, mut gen_queue) = resources;
 
     let mut player_pos = player.single().pos;
     if player_pos.x < 0 {
@@ -596,19 +832,13 @@ pub fn update_manager_pos(
         }
 
         if new_diff != IVec2::ZERO {
-            task_executor.start(async move {
-                let file = File::open("assets/world/world").unwrap();
-                let mut buffered = BufReader::new(file);
-
-                let chunks: HashMap<IVec2, Chunk> =
-                    bincode::deserialize_from(&mut buffered).unwrap();
-                (chunks, new_diff)
-            });
+            task_executor.start(async move { (load_chunks(), new_diff) });
         }
     } else {
         match task_executor.poll() {
             Poll::Ready(v) => {
                 if let Ok((mut file_chunks, diff)) = v {
+                    chunk_manager.spawned.save_collected(&mut file_chunks);
                     let chunk_textures = chunk_textures.single();
                     for _ in 0..diff.x.abs() {
                         chunk_manager.move_manager(
@@ -617,7 +847,8 @@ pub fn update_manager_pos(
                             &chunk_textures,
                             &image_entities,
                             &mut file_chunks,
-                            (MoveDir::X(diff.x.signum()), materials, &generator),
+                            MoveDir::X(diff.x.signum()),
+                            &mut gen_queue,
                         );
                     }
 
@@ -628,16 +859,13 @@ pub fn update_manager_pos(
                             &chunk_textures,
                             &image_entities,
                             &mut file_chunks,
-                            (MoveDir::Y(diff.y.signum()), materials, &generator),
+                            MoveDir::Y(diff.y.signum()),
+                            &mut gen_queue,
                         );
                     }
 
                     let pool = AsyncComputeTaskPool::get();
-                    saving_task.0 = Some(pool.spawn(async move {
-                        let file = File::create("assets/world/world").unwrap();
-                        let mut buffered = BufWriter::new(file);
-                        bincode::serialize_into(&mut buffered, &file_chunks).unwrap();
-                    }));
+                    saving_task.0 = Some(pool.spawn(async move { save_chunks(&file_chunks) }));
                 }
             }
             Poll::Pending => {}
@@ -662,17 +890,30 @@ pub struct ExtractedTextureUpdate {
 fn extract_chunk_texture_updates(
     chunk_manager: Extract<Res<ChunkManager>>,
     dirty_rects: Extract<Res<DirtyRects>>,
+    animated: Extract<Res<AnimatedRects>>,
     mut extracted_updates: ResMut<ExtractedTextureUpdates>,
 ) {
     for (chunk_pos, chunk) in chunk_manager.chunks.iter() {
-        if let Some(rect) = dirty_rects.render.get(chunk_pos) {
+        //Animated atoms are uploaded by tiles when each tile has it's turn,
+        //skipping the ones already inside the changed rect
+        let render = dirty_rects.render.get(chunk_pos).copied();
+        let animated_rects = animated
+            .rects
+            .get(chunk_pos)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|rect| render.is_none_or(|render| render.union(*rect) != render));
+
+        for rect in render.into_iter().chain(animated_rects) {
             let id = chunk.texture.id();
             let mut data = SmallVec::new();
 
             for y in rect.min.y..=rect.max.y {
                 for x in rect.min.x..=rect.max.x {
                     let pos = IVec2::new(x as i32, y as i32);
-                    let color = chunk.atoms[pos.d1()].color;
+                    let global = *chunk_pos * CHUNK_LENGHT as i32 + pos;
+                    let color = animated.color(&chunk.atoms[pos.d1()], global);
                     data.extend_from_slice(&color)
                 }
             }
@@ -723,6 +964,22 @@ fn prepare_chunk_gpu_textures(
     }
 }
 
+/// Updates the background textures where the walls changed
+pub fn update_backgrounds(
+    mut images: ResMut<Assets<Image>>,
+    chunk_manager: Res<ChunkManager>,
+    mut dirty_rects: ResMut<DirtyRects>,
+) {
+    for (index, rect) in dirty_rects.background.drain() {
+        let Some(chunk) = chunk_manager.chunks.get(&index) else {
+            continue;
+        };
+        if let Some(image) = images.get_mut(&chunk.background_texture) {
+            chunk.update_background(image, rect);
+        }
+    }
+}
+
 fn clear_render_rect(mut dirty_rects: ResMut<DirtyRects>) {
     dirty_rects.render = HashMap::new();
 }
@@ -742,7 +999,9 @@ impl Plugin for ChunkManagerPlugin {
                 Update,
                 (
                     update_manager_pos,
-                    add_colliders.after(update_has_collider),
+                    collect_spawns.after(update_manager_pos),
+                    update_backgrounds,
+                    update_colliders.after(update_has_collider),
                     remove_colliders.after(update_has_collider),
                     update_has_collider,
                 )
@@ -753,9 +1012,9 @@ impl Plugin for ChunkManagerPlugin {
                 clear_render_rect.run_if(in_state(GameState::Game)),
             )
             .init_resource::<HasCollider>()
+            .init_resource::<ChunkColliders>()
             .init_resource::<ChunkManager>()
-            .init_resource::<DirtyRects>()
-            .init_resource::<Generator>();
+            .init_resource::<DirtyRects>();
 
         if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
             render_app
@@ -12,6 +12,40 @@ pub const QUARTER_CHUNK_LEN: usize = CHUNK_LEN / 4;
 // Actor consts
 
 pub const UP_WALK_HEIGHT: usize = 3;
+//Push up each tick of a actor fully in a liquid of density 1, players fall with 0.36
+pub const ACTOR_BUOYANCY: f32 = 0.6;
+//Part of the velocity lost each tick fully in a liquid of density 1
+pub const ACTOR_LIQUID_DRAG: f32 = 0.15;
+//Slowest actor that splashes the liquids it goes through
+pub const ACTOR_SPLASH_SPEED: f32 = 2.;
+//Chance of each liquid atom on the surface splashing
+pub const ACTOR_SPLASH_CHANCE: f32 = 0.3;
+
+// Creature consts
+pub const CREATURE_MAX: usize = 12;
+//Random positions tried for spawning creatures each tick
+pub const CREATURE_SPAWN_TRIES: usize = 4;
+//Creatures spawn at least this far from the player, out of view
+pub const CREATURE_SPAWN_DISTANCE: f32 = 180.;
+//Atoms below the spawn position a walking creature looks for ground
+pub const CREATURE_SPAWN_DROP: usize = 64;
+pub const CREATURE_DESPAWN_DISTANCE: f32 = 800.;
+pub const CREATURE_GRAVITY: f32 = 0.36;
+//Chance each tick of a wandering creature picking a new direction
+pub const CREATURE_TURN_CHANCE: f32 = 0.01;
+pub const CREATURE_JUMP: f32 = 4.;
+//Seconds between a creature hurting the player
+pub const CREATURE_ATTACK_COOLDOWN: f32 = 1.;
+
+// Pathfinding consts
+//Positions a path search explores before settling for the closest one to the goal
+pub const PATH_MAX_NODES: usize = 20000;
+//Seconds before a path is found again, as the terrain changes
+pub const PATH_REPLAN_TIME: f32 = 1.;
+//Distance the goal moves before the path is found again right away
+pub const PATH_GOAL_MOVED: f32 = 8.;
+//Distance a waypoint counts as reached from
+pub const PATH_REACHED: f32 = 2.;
 
 // Player consts
 pub const FUEL_MAX: f32 = 50.;
@@ -25,6 +59,12 @@ pub const PRESSED_JUMP_MAG: f32 = 0.2;
 pub const TIME_JUMP_PRESSED: f32 = 0.8;
 pub const RUN_SPEED: f32 = 2.5;
 
+//Part of the player in liquids it swims at
+pub const SWIM_SUBMERGED: f32 = 0.5;
+pub const SWIM_SPEED: f32 = 1.5;
+pub const SWIM_FORCE: f32 = 0.8;
+pub const SWIM_MAX: f32 = 2.5;
+
 pub const TOOL_DISTANCE: f32 = 32.;
 pub const TOOL_RANGE: f32 = 16.;
 
@@ -47,15 +87,75 @@ pub const LOAD_HEIGHT: i32 = 18;
 pub const _CAMERA_SPEED: f32 = 10.;
 
 //Layers
+pub const BACKGROUND_LAYER: f32 = -10.;
 pub const PLAYER_LAYER: f32 = 1.;
 pub const PARTICLE_LAYER: f32 = 10.;
 pub const AUTOMATA_LAYER: f32 = 100.;
 pub const RIGIDBODY_LAYER: f32 = 1000.;
+pub const LIGHT_LAYER: f32 = 2000.;
+
+// Rigidbody consts
+//Chance of a damaging liquid atom dissolving a rigidbody atom each tick, times it's damage
+pub const DISSOLVE_CHANCE: f32 = 0.002;
+//Pieces with less atoms than this break into particles
+pub const MIN_PIECE_ATOMS: usize = 6;
+//Gravity of the rapier world, at 1 pixel per meter, for the weight of the displaced liquid
+pub const BUOYANCY_GRAVITY: f32 = 9.81;
+//Damping of fully submerged rigidbodies, less when they are partially submerged
+pub const LIQUID_LINEAR_DRAG: f32 = 2.;
+pub const LIQUID_ANGULAR_DRAG: f32 = 3.;
+
+// Particle consts
+//Particles moved by each task
+pub const PARTICLE_BATCH: usize = 256;
+//Slowest impact that splashes, in atoms per tick
+pub const SPLASH_MIN_SPEED: f32 = 6.;
+pub const SPLASH_MAX_ATOMS: usize = 8;
+//Distance from the impact of the atoms that can splash
+pub const SPLASH_RADIUS: i32 = 2;
+//Part of the impact speed the splashed atoms keep
+pub const SPLASH_SPEED: f32 = 0.3;
+//Max angle from straight up the splashed atoms go in, in radians
+pub const SPLASH_SPREAD: f32 = 0.7;
+
+// Emitter consts
+//Random atoms of each chunk checked for surface emitters each tick
+pub const SURFACE_EMIT_SAMPLES: usize = 16;
+//Visual particles alive at once, new ones aren't emitted past it
+pub const MAX_VISUAL_PARTICLES: usize = 4096;
+
+// Lighting consts
+pub const LIGHT_MAX: u8 = 64;
+//Sunlight only comes from the top of the loaded area when it's above this global y
+pub const SUNLIGHT_DEPTH: i32 = 0;
+//Max number of atoms relit each frame, the rest waits for the next frames
+pub const LIGHT_BUDGET: usize = 300_000;
+
+//Frames between the uploads of the animated atom colors of a chunk tile
+pub const COLOR_ANIMATION_INTERVAL: i32 = 2;
+//Lenght of the square tiles the animated atoms are uploaded by, CHUNK_LENGHT must be divisible by it
+pub const COLOR_ANIMATION_TILE: usize = 16;
+
+// Map consts
+//Atoms per map pixel on each axis, CHUNK_LENGHT must be divisible by it
+pub const MAP_SCALE: usize = 8;
+//Chunks around the player marked as explored
+pub const MAP_EXPLORE_RADIUS: i32 = 3;
+//Frames between the minimap redraws of changed chunks
+pub const MINIMAP_INTERVAL: u32 = 10;
+pub const MAP_PAN_SPEED: f32 = 8.;
+pub const MAP_ZOOM_LOWER_BOUND: f32 = 0.5;
+pub const MAP_ZOOM_UPPER_BOUND: f32 = 8.;
+
+// Capture consts
+//Simulation ticks between time-lapse frames, if not given with --timelapse
+pub const TIME_LAPSE_TICKS: u32 = 30;
+
+// Structure consts
+//Atoms between the coarse samples of a surface structure ground search, ground thinner than this can be missed
+pub const STRUCTURE_SEARCH_STEP: i32 = 8;
 
 //Buttons
 pub const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
 pub const HOVERED_BUTTON: Color = Color::srgb(0.25, 0.25, 0.25);
 pub const PRESSED_BUTTON: Color = Color::srgb(0.35, 0.75, 0.35);
-
-//How much the rigidbody needs to be submerged to totally cut of gravity
-//pub const STOP_RATE: f32 = 0.05;
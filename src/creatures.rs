@@ -0,0 +1,450 @@
+use itertools::Itertools;
+
+use crate::prelude::*;
+
+/// A creature, loaded from `assets/creatures.ron`.
+/// Speeds are in atoms per tick and depths in global atoms, with y going down.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CreatureDef {
+    pub width: u8,
+    pub height: u8,
+    /// Sprite sheet, with `columns` by `rows` frames of `frame` size
+    pub sheet: String,
+    pub frame: (u32, u32),
+    pub columns: u32,
+    pub rows: u32,
+    /// First and last frames of each animation
+    pub idle: (usize, usize),
+    pub moving: (usize, usize),
+    pub speed: f32,
+    pub health: f32,
+    /// Damage done to the player when touching it
+    #[serde(default)]
+    pub damage: f32,
+    pub behaviour: Behaviour,
+    /// Chasers fly up with a jetpack to reach the player, instead of only walking
+    #[serde(default)]
+    pub jetpack: bool,
+    /// Distance the player is noticed from, in atoms
+    #[serde(default = "default_sight")]
+    pub sight: f32,
+    pub spawn: SpawnRule,
+}
+
+fn default_sight() -> f32 {
+    64.
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Behaviour {
+    /// Walks around, turning on walls
+    Wander,
+    /// Wanders until it sees the player, then runs away
+    Flee,
+    /// Wanders until it sees the player, then goes for it
+    Chase,
+    /// Swims around in liquids, flopping when out of them
+    Swim,
+    /// Flies between spots it can reach, without gravity
+    Fly,
+}
+
+/// Where a creature can spawn
+#[derive(Clone, Debug, Deserialize)]
+pub struct SpawnRule {
+    #[serde(default)]
+    pub min_depth: Option<i32>,
+    #[serde(default)]
+    pub max_depth: Option<i32>,
+    /// Biome names from `assets/gen.ron`, any biome if empty
+    #[serde(default)]
+    pub biomes: Vec<String>,
+    /// Chance of spawning each time it's tried
+    pub chance: f32,
+    /// Max of this creature alive at once
+    pub max: usize,
+}
+
+impl SpawnRule {
+    fn allows(&self, pos: IVec2, gen_queue: &GenQueue) -> bool {
+        self.min_depth.is_none_or(|min| pos.y >= min)
+            && self.max_depth.is_none_or(|max| pos.y <= max)
+            && (self.biomes.is_empty()
+                || gen_queue
+                    .biome_at(pos)
+                    .is_some_and(|biome| self.biomes.iter().any(|name| name == biome)))
+    }
+}
+
+/// The creature definitions and their sprite sheets
+#[derive(Resource, Default)]
+pub struct CreatureDefs {
+    pub defs: Vec<CreatureDef>,
+    sheets: Vec<(Handle<Image>, Handle<TextureAtlasLayout>)>,
+}
+
+pub fn creatures_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut texture_atlases: ResMut<Assets<TextureAtlasLayout>>,
+) {
+    let mut defs = CreatureDefs::default();
+
+    if let Ok(bytes) = std::fs::read("assets/creatures.ron") {
+        let loaded = ron::de::from_bytes::<HashMap<String, CreatureDef>>(&bytes).unwrap();
+        for def in loaded.into_values() {
+            let layout =
+                TextureAtlasLayout::from_grid(def.frame.into(), def.columns, def.rows, None, None);
+            defs.sheets
+                .push((asset_server.load(&def.sheet), texture_atlases.add(layout)));
+            defs.defs.push(def);
+        }
+    }
+
+    commands.insert_resource(defs);
+}
+
+#[derive(Component)]
+pub struct Creature {
+    pub def: usize,
+    /// Direction it's wandering in
+    dir: Vec2,
+    /// Position on the last tick, to know when it's stuck
+    last_pos: IVec2,
+    /// Seconds until it can hurt the player again
+    cooldown: f32,
+}
+
+/// Center of the actor, for the creature sprite and collider
+fn creature_translation(actor: &Actor) -> Vec3 {
+    let center = actor.pos.as_vec2() + vec2(actor.width as f32, actor.height as f32) / 2.;
+    vec3(center.x, -center.y, PLAYER_LAYER)
+}
+
+/// If all the actor atoms are void, or liquid for swimming creatures
+fn fits(chunk_manager: &ChunkManager, actor: &Actor, liquid: bool) -> bool {
+    (0..actor.width as i32)
+        .cartesian_product(0..actor.height as i32)
+        .all(|(x, y)| {
+            chunk_manager
+                .get_atom(&global_to_chunk(actor.pos + ivec2(x, y)))
+                .is_some_and(|atom| {
+                    if liquid {
+                        atom.is_liquid()
+                    } else {
+                        atom.is_void()
+                    }
+                })
+        })
+}
+
+pub fn spawn_creatures(
+    mut commands: Commands,
+    chunk_manager: Res<ChunkManager>,
+    gen_queue: Res<GenQueue>,
+    defs: Res<CreatureDefs>,
+    creatures: Query<&Creature>,
+    player: Query<&Actor, With<Player>>,
+) {
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+    if defs.defs.is_empty() || creatures.iter().count() >= CREATURE_MAX {
+        return;
+    }
+
+    let min = chunk_manager.pos * CHUNK_LENGHT as i32;
+    let size = ivec2(LOAD_WIDTH, LOAD_HEIGHT) * CHUNK_LENGHT as i32;
+
+    for _ in 0..CREATURE_SPAWN_TRIES {
+        let index = fastrand::usize(..defs.defs.len());
+        let def = &defs.defs[index];
+        if fastrand::f32() >= def.spawn.chance
+            || creatures.iter().filter(|c| c.def == index).count() >= def.spawn.max
+        {
+            continue;
+        }
+
+        let mut actor = Actor {
+            width: def.width,
+            height: def.height,
+            pos: min + ivec2(fastrand::i32(0..size.x), fastrand::i32(0..size.y)),
+            vel: Vec2::ZERO,
+            colliding: None,
+            submerged: 0.,
+        };
+
+        //Walking creatures spawn on the ground below
+        let walks = !matches!(def.behaviour, Behaviour::Swim | Behaviour::Fly);
+        if walks {
+            for _ in 0..CREATURE_SPAWN_DROP {
+                if on_ground(&chunk_manager, &actor) {
+                    break;
+                }
+                actor.pos.y += 1;
+            }
+        }
+
+        if actor.pos.as_vec2().distance(player.pos.as_vec2()) < CREATURE_SPAWN_DISTANCE
+            || !def.spawn.allows(actor.pos, &gen_queue)
+            || !fits(&chunk_manager, &actor, def.behaviour == Behaviour::Swim)
+            || walks && !on_ground(&chunk_manager, &actor)
+        {
+            continue;
+        }
+
+        let (image, layout) = defs.sheets[index].clone();
+        let mut creature = commands.spawn((
+            Creature {
+                def: index,
+                dir: Vec2::ZERO,
+                last_pos: actor.pos,
+                cooldown: 0.,
+            },
+            Life(def.health),
+            Sprite::from_atlas_image(
+                image,
+                TextureAtlas {
+                    index: def.idle.0,
+                    layout,
+                },
+            ),
+            Transform::from_translation(creature_translation(&actor)),
+            AnimationIndices {
+                first: def.idle.0,
+                last: def.idle.1,
+            },
+            AnimationTimer(Timer::from_seconds(0.15, TimerMode::Repeating)),
+            RapierRigidbody::Fixed,
+            LockedAxes::ROTATION_LOCKED,
+            Collider::cuboid(def.width as f32 / 2., def.height as f32 / 2.),
+            actor,
+        ));
+        match def.behaviour {
+            Behaviour::Chase if def.jetpack => {
+                creature.insert(Pathfinder::new(NavAbility::Jetpack));
+            }
+            Behaviour::Chase => {
+                creature.insert(Pathfinder::new(NavAbility::Walk));
+            }
+            Behaviour::Fly => {
+                creature.insert(Pathfinder::new(NavAbility::Fly));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sets the creatures velocity from their behaviour, before the actors move
+pub fn update_creatures(
+    chunk_manager: Res<ChunkManager>,
+    defs: Res<CreatureDefs>,
+    mut creatures: Query<(&mut Actor, &mut Creature, Option<&mut Pathfinder>), Without<Player>>,
+    player: Query<(Entity, &Actor), With<Player>>,
+    mut ev_damage: EventWriter<DamageEvent>,
+    time: Res<Time>,
+) {
+    let Ok((player_ent, player)) = player.get_single() else {
+        return;
+    };
+    let player_rect = IRect::from_corners(
+        player.pos,
+        player.pos + ivec2(player.width as i32, player.height as i32),
+    );
+    let player_center = player_rect.center().as_vec2();
+
+    for (mut actor, mut creature, pathfinder) in &mut creatures {
+        let def = &defs.defs[creature.def];
+        let rect = IRect::from_corners(
+            actor.pos,
+            actor.pos + ivec2(actor.width as i32, actor.height as i32),
+        );
+
+        //Touching the player
+        creature.cooldown = (creature.cooldown - time.delta_secs()).max(0.);
+        if def.damage > 0. && creature.cooldown == 0. && !rect.intersect(player_rect).is_empty() {
+            ev_damage.send(DamageEvent::new(player_ent, def.damage));
+            creature.cooldown = CREATURE_ATTACK_COOLDOWN;
+        }
+
+        //Wandering direction changes now and then
+        let turned = creature.dir == Vec2::ZERO || fastrand::f32() < CREATURE_TURN_CHANCE;
+        if turned {
+            creature.dir = match def.behaviour {
+                Behaviour::Swim | Behaviour::Fly => {
+                    Vec2::from_angle(fastrand::f32() * std::f32::consts::TAU)
+                }
+                _ => vec2(if fastrand::bool() { 1. } else { -1. }, 0.),
+            };
+        }
+
+        let to_player = player_center - rect.center().as_vec2();
+        let sees = to_player.length() < def.sight;
+
+        let mut waypoint = None;
+        if let Some(mut pathfinder) = pathfinder {
+            pathfinder.goal = match def.behaviour {
+                //Flyers find their way to a spot they are heading to, a new one when they turn
+                Behaviour::Fly if turned || pathfinder.goal.is_none() => {
+                    Some(actor.pos + (creature.dir * def.sight).as_ivec2())
+                }
+                Behaviour::Fly => pathfinder.goal,
+                //Chasers find their way to where they would stand beside the player
+                _ => sees.then(|| {
+                    player.pos
+                        + ivec2(
+                            (player.width as i32 - actor.width as i32) / 2,
+                            player.height as i32 - actor.height as i32,
+                        )
+                }),
+            };
+            waypoint = pathfinder.waypoint(actor.pos);
+        }
+        let to_waypoint =
+            waypoint.map(|waypoint| (waypoint - actor.pos).as_vec2().normalize_or_zero());
+
+        let dir = match def.behaviour {
+            Behaviour::Chase if sees => to_waypoint.unwrap_or(to_player.normalize_or_zero()),
+            Behaviour::Flee if sees => -to_player.normalize_or_zero(),
+            Behaviour::Fly => to_waypoint.unwrap_or(creature.dir),
+            _ => creature.dir,
+        };
+
+        let on_ground = on_ground(&chunk_manager, &actor);
+        let stuck = actor.pos.x == creature.last_pos.x;
+        creature.last_pos = actor.pos;
+
+        match def.behaviour {
+            Behaviour::Fly => {
+                //Moving actors lose their velocity when they hit something
+                if actor.vel == Vec2::ZERO {
+                    creature.dir = -creature.dir;
+                }
+                actor.vel = dir * def.speed;
+            }
+            Behaviour::Swim if actor.submerged > 0.5 => {
+                //Turns around instead of leaving the liquid
+                let ahead = rect.center().as_vec2()
+                    + dir * (vec2(actor.width as f32, actor.height as f32) / 2. + 1.);
+                let liquid_ahead = chunk_manager
+                    .get_atom(&global_to_chunk(ahead.as_ivec2()))
+                    .is_some_and(|atom| atom.is_liquid());
+
+                if liquid_ahead {
+                    actor.vel = dir * def.speed;
+                } else {
+                    creature.dir = -creature.dir;
+                    actor.vel = Vec2::ZERO;
+                }
+            }
+            Behaviour::Swim => {
+                //Flops around on land
+                if on_ground && fastrand::f32() < CREATURE_TURN_CHANCE * 4. {
+                    actor.vel = vec2(dir.x.signum() * def.speed, -CREATURE_JUMP / 2.);
+                }
+                if actor.vel.y < PLAYER_TERM_VEL as f32 {
+                    actor.vel.y += CREATURE_GRAVITY;
+                }
+            }
+            _ => {
+                actor.vel.x = if dir.x.abs() > 0.1 {
+                    dir.x.signum() * def.speed
+                } else {
+                    0.
+                };
+                if actor.vel.y < PLAYER_TERM_VEL as f32 {
+                    actor.vel.y += CREATURE_GRAVITY;
+                }
+                //Flies up to waypoints above it, like the player jetpack
+                if def.jetpack && sees && dir.y < -0.5 {
+                    actor.vel.y = (actor.vel.y - JETPACK_FORCE).clamp(-JETPACK_MAX, f32::MAX);
+                }
+
+                //Jumps over walls when going somewhere, wanderers just turn around
+                if on_ground && stuck && actor.vel.x != 0. {
+                    if def.behaviour == Behaviour::Wander || !sees {
+                        creature.dir = -creature.dir;
+                    } else {
+                        actor.vel.y = -CREATURE_JUMP;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Moves the creature sprites to their actors, facing where they go
+pub fn update_creature_sprites(
+    defs: Res<CreatureDefs>,
+    mut creatures: Query<(
+        &Actor,
+        &Creature,
+        &mut Transform,
+        &mut Sprite,
+        &mut AnimationIndices,
+    )>,
+) {
+    for (actor, creature, mut transform, mut sprite, mut indices) in &mut creatures {
+        let def = &defs.defs[creature.def];
+
+        transform.translation = creature_translation(actor);
+        if actor.vel.x != 0. {
+            sprite.flip_x = actor.vel.x < 0.;
+        }
+
+        let (first, last) = if actor.vel.x.abs() >= 1. || actor.vel.y.abs() >= 1. {
+            def.moving
+        } else {
+            def.idle
+        };
+        (indices.first, indices.last) = (first, last);
+    }
+}
+
+/// Removes dead creatures, and the ones too far from the player or outside the loaded area
+pub fn despawn_creatures(
+    mut commands: Commands,
+    chunk_manager: Res<ChunkManager>,
+    creatures: Query<(Entity, &Actor, &Life), With<Creature>>,
+    player: Query<&Actor, With<Player>>,
+    mut ev_burst: EventWriter<BurstEvent>,
+) {
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+
+    for (ent, actor, life) in &creatures {
+        let loaded = chunk_manager
+            .get_atom(&global_to_chunk(actor.pos))
+            .is_some();
+        let far = actor.pos.as_vec2().distance(player.pos.as_vec2()) > CREATURE_DESPAWN_DISTANCE;
+
+        if life.0 <= 0. {
+            let center = creature_translation(actor);
+            ev_burst.send(BurstEvent::new("dust", vec2(center.x, -center.y)));
+        }
+        if life.0 <= 0. || !loaded || far {
+            commands.entity(ent).despawn_recursive();
+        }
+    }
+}
+
+pub struct CreaturesPlugin;
+impl Plugin for CreaturesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CreatureDefs>()
+            .add_systems(OnEnter(GameState::Loading), creatures_setup)
+            .add_systems(
+                FixedUpdate,
+                (
+                    despawn_creatures.after(unfill_actors).after(damage_feed),
+                    spawn_creatures.after(despawn_creatures),
+                    update_creatures
+                        .after(spawn_creatures)
+                        .before(update_actors),
+                    update_creature_sprites.after(update_actors),
+                )
+                    .run_if(in_state(GameState::Game)),
+            );
+    }
+}
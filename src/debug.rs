@@ -185,6 +185,19 @@ fn render_actors(mut gizmos: Gizmos, actors: Query<&Actor>) {
     }
 }
 
+fn render_paths(mut gizmos: Gizmos, pathfinders: Query<(&Actor, &Pathfinder)>) {
+    for (actor, pathfinder) in pathfinders.iter() {
+        let center = vec2(actor.width as f32, actor.height as f32) / 2.;
+        gizmos.linestrip_2d(
+            pathfinder.path.iter().map(|pos| {
+                let pos = pos.as_vec2() + center;
+                vec2(pos.x, -pos.y)
+            }),
+            Color::srgba(0.25, 0.75, 0.25, 0.5),
+        );
+    }
+}
+
 fn _camera(keys: Res<ButtonInput<KeyCode>>, mut camera_q: Query<&mut Transform, With<Camera>>) {
     let x = -(keys.pressed(KeyCode::KeyA) as u8 as f32) + keys.pressed(KeyCode::KeyD) as u8 as f32;
     let y = -(keys.pressed(KeyCode::KeyS) as u8 as f32) + keys.pressed(KeyCode::KeyW) as u8 as f32;
@@ -280,6 +293,58 @@ pub fn grab_rigidbodies(
     }
 }
 
+/// Blows up the rigidbodies around the cursor
+pub fn explode_rigidbodies(
+    window: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform)>,
+    input: Res<ButtonInput<KeyCode>>,
+    mut ev_carve: EventWriter<CarveEvent>,
+) {
+    if !input.just_pressed(KeyCode::KeyX) {
+        return;
+    }
+
+    let (camera, camera_transform) = camera_q.single();
+    let Some(cursor_position) = window.single().cursor_position() else {
+        return;
+    };
+    let Ok(world_position) = camera.viewport_to_world_2d(camera_transform, cursor_position) else {
+        return;
+    };
+
+    ev_carve.send(CarveEvent {
+        center: vec2(world_position.x, -world_position.y),
+        radius: 10.,
+        force: 3.,
+        follow: None,
+    });
+}
+
+/// Cuts the terrain around the cursor into a rigidbody
+pub fn cut_terrain_tool(
+    window: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform)>,
+    input: Res<ButtonInput<KeyCode>>,
+    mut ev_cut: EventWriter<CutEvent>,
+) {
+    if !input.just_pressed(KeyCode::KeyC) {
+        return;
+    }
+
+    let (camera, camera_transform) = camera_q.single();
+    let Some(cursor_position) = window.single().cursor_position() else {
+        return;
+    };
+    let Ok(world_position) = camera.viewport_to_world_2d(camera_transform, cursor_position) else {
+        return;
+    };
+
+    ev_cut.send(CutEvent {
+        center: vec2(world_position.x, -world_position.y),
+        radius: 12.,
+    });
+}
+
 #[derive(Resource, Default)]
 pub struct Grabbed(Option<Entity>);
 
@@ -292,8 +357,11 @@ impl Plugin for DebugPlugin {
                 render_dirty_rects_gizmos.after(update_actors),
                 brush.after(chunk_manager_update),
                 render_actors.after(update_actors),
+                render_paths.after(poll_paths),
                 prev_mpos.after(brush),
                 grab_rigidbodies,
+                explode_rigidbodies,
+                cut_terrain_tool,
                 //_camera
             )
                 .run_if(in_state(GameState::Game)),
@@ -0,0 +1,291 @@
+use std::f64::consts::TAU;
+
+use bevy::math::{dvec2, DVec2};
+
+use crate::prelude::*;
+
+/// Material placed in the terrain after the base generation
+#[derive(Clone, Debug, Deserialize)]
+pub struct Deposit {
+    pub id: u8,
+    pub shape: DepositShape,
+
+    /// The world is split in square cells of this size, in atoms
+    pub cell_size: u32,
+    /// Average number of deposits in each cell
+    pub frequency: f64,
+    /// Radius of blobs and pools, or lenght of veins, in atoms
+    pub size: (f64, f64),
+
+    /// Depth range, in global atoms, where the deposit center can be
+    #[serde(default)]
+    pub min_depth: Option<f64>,
+    #[serde(default)]
+    pub max_depth: Option<f64>,
+    /// Names of the biomes the deposit center can be, if none it can be anywhere
+    #[serde(default)]
+    pub biomes: Option<Vec<String>>,
+    /// Ids the deposit can replace, if none it replaces any non void atom
+    #[serde(default)]
+    pub replace: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum DepositShape {
+    /// Round blob with a wobbly edge
+    Blob,
+    /// Long curved line, thinning at the ends
+    Vein { thickness: f64 },
+    /// Half filled pocket, with air above the material
+    Pool,
+}
+
+/// A single placed deposit
+#[derive(Clone, Copy, Debug)]
+struct Placed {
+    center: DVec2,
+    size: f64,
+    angle: f64,
+    /// Random value used to vary the shape
+    wobble: f64,
+}
+
+impl Deposit {
+    fn extent(&self) -> f64 {
+        match self.shape {
+            DepositShape::Blob => self.size.1 * 1.4,
+            DepositShape::Vein { thickness } => self.size.1 * 0.65 + thickness,
+            DepositShape::Pool => self.size.1,
+        }
+    }
+
+    /// Gets all deposits which center is inside the cell
+    fn placed(&self, cell: IVec2, seed: u32, generator: &Generator) -> Vec<Placed> {
+        let rand = |salt: u32| hash(cell, seed.wrapping_add(salt));
+
+        let count = self.frequency.floor() as u32 + (rand(0) < self.frequency.fract()) as u32;
+
+        let mut placed = vec![];
+        for i in 0..count {
+            let salt = (i + 1) * 8;
+            let center =
+                (cell.as_dvec2() + dvec2(rand(salt + 1), rand(salt + 2))) * self.cell_size as f64;
+
+            if self.min_depth.is_some_and(|min| center.y < min)
+                || self.max_depth.is_some_and(|max| center.y >= max)
+            {
+                continue;
+            }
+
+            if let Some(biomes) = &self.biomes {
+                let biome = generator.terrain(center.as_ivec2()).biome;
+                let Some(biome) = generator.config.biomes.get(biome) else {
+                    continue;
+                };
+                if !biomes.contains(&biome.name) {
+                    continue;
+                }
+            }
+
+            placed.push(Placed {
+                center,
+                size: self.size.0 + (self.size.1 - self.size.0) * rand(salt + 3),
+                angle: rand(salt + 4) * TAU,
+                wobble: rand(salt + 5),
+            });
+        }
+
+        placed
+    }
+
+    /// Gets the id the deposit puts on a global position, none if outside of it
+    fn shape_at(&self, placed: &Placed, pos: DVec2) -> Option<u8> {
+        let offset = pos - placed.center;
+
+        match self.shape {
+            DepositShape::Blob => {
+                let angle = offset.y.atan2(offset.x);
+                let radius = placed.size
+                    * (1. + 0.25 * (angle * 3. + placed.wobble * TAU).sin())
+                    * (1. + 0.1 * (angle * 7. + placed.angle).sin());
+
+                (offset.length() < radius).then_some(self.id)
+            }
+            DepositShape::Vein { thickness } => {
+                let dir = DVec2::from_angle(placed.angle);
+                let along = offset.dot(dir) / placed.size + 0.5;
+                if !(0.0..1.).contains(&along) {
+                    return None;
+                }
+
+                // Bends the vein and makes it thinner at it's ends
+                let bend = (along * TAU * (0.5 + placed.wobble)).sin() * placed.size * 0.15;
+                let half_width = thickness / 2. * (1. - (along * 2. - 1.).powi(2)).sqrt();
+                let across = offset.perp_dot(dir) - bend;
+
+                (across.abs() < half_width).then_some(self.id)
+            }
+            DepositShape::Pool => {
+                let scaled = offset / dvec2(1., 0.5 + placed.wobble * 0.25);
+                if scaled.length() >= placed.size {
+                    return None;
+                }
+
+                Some(if offset.y >= 0. { self.id } else { 0 })
+            }
+        }
+    }
+
+    fn can_replace(&self, id: u8) -> bool {
+        match &self.replace {
+            Some(replace) => replace.contains(&id),
+            None => id != 0 || self.shape == DepositShape::Pool,
+        }
+    }
+
+    /// Places this deposit on the chunk, including deposits started on the chunks around
+    pub fn place(&self, chunk: &mut GenChunk, seed: u32, generator: &Generator) {
+        if self.cell_size == 0 {
+            return;
+        }
+
+        let index = chunk.index;
+        let chunk_min = (index * CHUNK_LENGHT as i32).as_dvec2();
+        let chunk_max = chunk_min + CHUNK_LENGHT as f64;
+        let extent = self.extent();
+        let cell_size = self.cell_size as f64;
+
+        let min_cell = ((chunk_min - extent) / cell_size).floor().as_ivec2();
+        let max_cell = ((chunk_max + extent) / cell_size).floor().as_ivec2();
+
+        for cell_y in min_cell.y..=max_cell.y {
+            for cell_x in min_cell.x..=max_cell.x {
+                for placed in self.placed(ivec2(cell_x, cell_y), seed, generator) {
+                    let min = ((placed.center - extent - chunk_min).floor().as_ivec2())
+                        .clamp(IVec2::ZERO, IVec2::splat(CHUNK_LENGHT as i32));
+                    let max = ((placed.center + extent - chunk_min).ceil().as_ivec2())
+                        .clamp(IVec2::ZERO, IVec2::splat(CHUNK_LENGHT as i32));
+
+                    for y in min.y..max.y {
+                        for x in min.x..max.x {
+                            let i = y as usize * CHUNK_LENGHT + x as usize;
+                            let pos = chunk_min + dvec2(x as f64, y as f64) + 0.5;
+
+                            if !self.can_replace(chunk.atoms[i].id) {
+                                continue;
+                            }
+                            if let Some(id) = self.shape_at(&placed, pos) {
+                                chunk.set(i, id);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Places the configured deposits of liquids, or of every other material, on the chunk, in order
+pub fn place_deposits(chunk: &mut GenChunk, generator: &Generator, liquids: bool) {
+    for (i, deposit) in generator.config.deposits.iter().enumerate() {
+        if chunk.is_liquid(deposit.id) != liquids {
+            continue;
+        }
+
+        let seed = generator
+            .config
+            .seed
+            .wrapping_add((i as u32 + 1).wrapping_mul(0x9E37_79B9));
+        deposit.place(chunk, seed, generator);
+    }
+}
+
+/// Material grown from the terrain surfaces, like grass or stalactites
+#[derive(Clone, Debug, Deserialize)]
+pub struct Decoration {
+    pub id: u8,
+    /// Ids it grows from
+    pub on: Vec<u8>,
+    pub side: DecorationSide,
+    /// Range of the atoms it grows
+    pub length: (u8, u8),
+    /// Chance of growing from each surface atom
+    pub chance: f64,
+
+    /// Depth range, in global atoms, where it grows
+    #[serde(default)]
+    pub min_depth: Option<f64>,
+    #[serde(default)]
+    pub max_depth: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum DecorationSide {
+    /// Grows up from atoms with air above
+    Floor,
+    /// Hangs down from atoms with air below
+    Ceiling,
+}
+
+impl Decoration {
+    /// Grows this decoration on the chunk, only inside of it
+    pub fn place(&self, chunk: &mut GenChunk, seed: u32) {
+        let dir = match self.side {
+            DecorationSide::Floor => -1,
+            DecorationSide::Ceiling => 1,
+        };
+        let inside = |y: i32| (0..CHUNK_LENGHT as i32).contains(&y);
+
+        // Found before growing, so decorations don't grow from each other
+        let mut grown = vec![];
+        for i in 0..CHUNK_LEN {
+            if !self.on.contains(&chunk.atoms[i].id) {
+                continue;
+            }
+
+            let pos = chunk.global(i);
+            if self.min_depth.is_some_and(|min| (pos.y as f64) < min)
+                || self.max_depth.is_some_and(|max| pos.y as f64 >= max)
+                || hash(pos, seed) >= self.chance
+            {
+                continue;
+            }
+
+            let (x, y) = ((i % CHUNK_LENGHT) as i32, (i / CHUNK_LENGHT) as i32);
+            if !inside(y + dir)
+                || !chunk.atoms[(y + dir) as usize * CHUNK_LENGHT + x as usize].is_void()
+            {
+                continue;
+            }
+
+            let range = (self.length.1 - self.length.0) as f64 + 1.;
+            let length = self.length.0 + (hash(pos, seed.wrapping_add(1)) * range) as u8;
+            grown.push((x, y, length.min(self.length.1)));
+        }
+
+        for (x, mut y, length) in grown {
+            for _ in 0..length {
+                y += dir;
+                if !inside(y) {
+                    break;
+                }
+                let i = y as usize * CHUNK_LENGHT + x as usize;
+                if !chunk.atoms[i].is_void() {
+                    break;
+                }
+                chunk.set(i, self.id);
+            }
+        }
+    }
+}
+
+/// Grows all the configured decorations on the chunk, in order
+pub fn place_decorations(chunk: &mut GenChunk, generator: &Generator) {
+    for (i, decoration) in generator.config.decorations.iter().enumerate() {
+        let seed = generator
+            .config
+            .seed
+            .wrapping_add((i as u32 + 1).wrapping_mul(0xC2B2_AE35));
+        decoration.place(chunk, seed);
+    }
+}
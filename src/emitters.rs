@@ -0,0 +1,345 @@
+use crate::prelude::*;
+
+/// A emitter of visual particles, loaded from `assets/emitters.ron`.
+/// Speeds are in atoms per second and angles in degrees, with y going down like the atoms.
+#[derive(Clone, Debug, Deserialize)]
+pub struct EmitterDef {
+    /// Particles each second while a `Emitter` is on
+    #[serde(default)]
+    pub rate: f32,
+    /// Particles at once on a burst
+    #[serde(default)]
+    pub burst: u32,
+    /// Range of the particles lifetime, in seconds
+    pub lifetime: (f32, f32),
+    pub speed: (f32, f32),
+    #[serde(default)]
+    pub direction: f32,
+    /// Angle around the direction the particles go in
+    #[serde(default = "default_spread")]
+    pub spread: f32,
+    #[serde(default)]
+    pub shape: SpawnShape,
+    /// Colors the particles go through over their life, evenly spaced
+    pub colors: Vec<[u8; 4]>,
+    #[serde(default)]
+    pub gravity: f32,
+    /// Part of the velocity lost each second
+    #[serde(default)]
+    pub drag: f32,
+    #[serde(default = "default_size")]
+    pub size: f32,
+    /// Material ids it bursts from on the atoms with air above,
+    /// and the chance of each sampled atom bursting
+    #[serde(default)]
+    pub surface: Vec<(u8, f32)>,
+}
+
+fn default_spread() -> f32 {
+    360.
+}
+
+fn default_size() -> f32 {
+    1.
+}
+
+/// Where the particles spawn, around the emitter position
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub enum SpawnShape {
+    #[default]
+    Point,
+    Circle(f32),
+    Rect(f32, f32),
+}
+
+impl SpawnShape {
+    fn sample(&self) -> Vec2 {
+        match *self {
+            SpawnShape::Point => Vec2::ZERO,
+            SpawnShape::Circle(radius) => {
+                Vec2::from_angle(fastrand::f32() * std::f32::consts::TAU)
+                    * radius
+                    * fastrand::f32().sqrt()
+            }
+            SpawnShape::Rect(width, height) => {
+                vec2(fastrand::f32() - 0.5, fastrand::f32() - 0.5) * vec2(width, height)
+            }
+        }
+    }
+}
+
+impl EmitterDef {
+    /// Color at the part of the particle life, multiplied by the tint
+    pub fn color(&self, life: f32, tint: Option<[u8; 4]>) -> Color {
+        let Some(last) = self.colors.len().checked_sub(1) else {
+            return Color::WHITE;
+        };
+
+        let at = life.clamp(0., 1.) * last as f32;
+        let (from, to) = (at.floor() as usize, (at.ceil() as usize).min(last));
+        let t = at.fract();
+
+        let [r, g, b, a] = [0, 1, 2, 3].map(|i| {
+            let from = self.colors[from][i] as f32;
+            let to = self.colors[to][i] as f32;
+            let tint = tint.map_or(1., |tint| tint[i] as f32 / 255.);
+            (from + (to - from) * t) / 255. * tint
+        });
+        Color::srgba(r, g, b, a)
+    }
+}
+
+/// The emitter definitions, by name
+#[derive(Resource, Default)]
+pub struct EmitterDefs {
+    pub defs: Vec<EmitterDef>,
+    names: HashMap<String, usize>,
+}
+
+impl EmitterDefs {
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+}
+
+pub fn emitters_setup(mut commands: Commands) {
+    let mut defs = EmitterDefs::default();
+
+    if let Ok(bytes) = std::fs::read("assets/emitters.ron") {
+        let loaded = ron::de::from_bytes::<HashMap<String, EmitterDef>>(&bytes).unwrap();
+        for (name, def) in loaded {
+            defs.names.insert(name, defs.defs.len());
+            defs.defs.push(def);
+        }
+    }
+
+    commands.insert_resource(defs);
+}
+
+/// Emits particles from the entity position while it's on
+#[derive(Component)]
+pub struct Emitter {
+    pub name: String,
+    pub on: bool,
+    /// Particles waiting to be emitted, as the rate isn't a whole number of particles each frame
+    pending: f32,
+}
+
+impl Emitter {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            on: false,
+            pending: 0.,
+        }
+    }
+}
+
+/// Spawns a burst of the named emitter particles
+#[derive(Event)]
+pub struct BurstEvent {
+    pub emitter: String,
+    /// Global position, with y going down like the atoms
+    pub pos: Vec2,
+    /// Velocity added to the particles, like from the thing bursting
+    pub velocity: Vec2,
+    /// Multiplies the emitter colors, like to make dust the color of the powder
+    pub tint: Option<[u8; 4]>,
+}
+
+impl BurstEvent {
+    pub fn new(emitter: &str, pos: Vec2) -> Self {
+        Self {
+            emitter: emitter.to_string(),
+            pos,
+            velocity: Vec2::ZERO,
+            tint: None,
+        }
+    }
+}
+
+/// A particle that is only drawn, it doesn't interact with the world
+pub struct VisualParticle {
+    pub emitter: usize,
+    /// Global position, with y going down like the atoms
+    pub pos: Vec2,
+    /// With y going down like the atoms
+    pub velocity: Vec2,
+    pub age: f32,
+    pub lifetime: f32,
+    pub tint: Option<[u8; 4]>,
+}
+
+/// The visual particles, drawn with the atom particles
+#[derive(Resource, Default)]
+pub struct VisualParticles(pub Vec<VisualParticle>);
+
+/// Spawns a particle of the emitter on the global position, with y going down.
+/// Does nothing when there are already `MAX_VISUAL_PARTICLES`.
+pub fn emit(
+    particles: &mut VisualParticles,
+    defs: &EmitterDefs,
+    emitter: usize,
+    pos: Vec2,
+    velocity: Vec2,
+    tint: Option<[u8; 4]>,
+) {
+    if particles.0.len() >= MAX_VISUAL_PARTICLES {
+        return;
+    }
+
+    let def = &defs.defs[emitter];
+    let range = |(min, max): (f32, f32)| min + (max - min) * fastrand::f32();
+
+    let angle = (def.direction + (fastrand::f32() - 0.5) * def.spread).to_radians();
+
+    particles.0.push(VisualParticle {
+        emitter,
+        pos: pos + def.shape.sample(),
+        velocity: velocity + Vec2::from_angle(angle) * range(def.speed),
+        age: 0.,
+        lifetime: range(def.lifetime),
+        tint,
+    });
+}
+
+pub fn update_emitters(
+    mut particles: ResMut<VisualParticles>,
+    mut emitters: Query<(&mut Emitter, &GlobalTransform)>,
+    mut ev_burst: EventReader<BurstEvent>,
+    defs: Res<EmitterDefs>,
+    time: Res<Time>,
+) {
+    for (mut emitter, transform) in &mut emitters {
+        let Some(index) = defs.get(&emitter.name) else {
+            continue;
+        };
+        if !emitter.on {
+            emitter.pending = 0.;
+            continue;
+        }
+
+        emitter.pending += defs.defs[index].rate * time.delta_secs();
+        let mut pos = transform.translation().xy();
+        pos.y *= -1.;
+
+        while emitter.pending >= 1. {
+            emitter.pending -= 1.;
+            emit(&mut particles, &defs, index, pos, Vec2::ZERO, None);
+        }
+    }
+
+    for event in ev_burst.read() {
+        let Some(index) = defs.get(&event.emitter) else {
+            error!("Emitter {} not found in assets/emitters.ron", event.emitter);
+            continue;
+        };
+
+        for _ in 0..defs.defs[index].burst {
+            emit(
+                &mut particles,
+                &defs,
+                index,
+                event.pos,
+                event.velocity,
+                event.tint,
+            );
+        }
+    }
+}
+
+pub fn update_visual_particles(
+    mut particles: ResMut<VisualParticles>,
+    defs: Res<EmitterDefs>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+
+    particles.0.retain_mut(|particle| {
+        particle.age += dt;
+
+        let def = &defs.defs[particle.emitter];
+        particle.velocity.y += def.gravity * dt;
+        particle.velocity *= (1. - def.drag * dt).max(0.);
+        particle.pos += particle.velocity * dt;
+
+        particle.age < particle.lifetime
+    });
+}
+
+/// Bursts the emitters from random surface atoms of their materials, like sparks from lava
+pub fn surface_emitters(
+    mut particles: ResMut<VisualParticles>,
+    chunk_manager: Res<ChunkManager>,
+    defs: Res<EmitterDefs>,
+) {
+    let surfaces: Vec<(usize, u8, f32)> = defs
+        .defs
+        .iter()
+        .enumerate()
+        .flat_map(|(index, def)| {
+            def.surface
+                .iter()
+                .map(move |(id, chance)| (index, *id, *chance))
+        })
+        .collect();
+    if surfaces.is_empty() {
+        return;
+    }
+
+    for (chunk_index, chunk) in &chunk_manager.chunks {
+        for _ in 0..SURFACE_EMIT_SAMPLES {
+            let i = fastrand::usize(..CHUNK_LEN);
+            let atom = chunk.atoms[i];
+
+            for &(index, id, chance) in &surfaces {
+                if atom.id != id || fastrand::f32() >= chance {
+                    continue;
+                }
+
+                let pos = *chunk_index * CHUNK_LENGHT as i32
+                    + ivec2((i % CHUNK_LENGHT) as i32, (i / CHUNK_LENGHT) as i32);
+                let air_above = chunk_manager
+                    .get_atom(&global_to_chunk(pos - IVec2::Y))
+                    .is_some_and(|atom| atom.is_void());
+
+                if air_above {
+                    for _ in 0..defs.defs[index].burst {
+                        emit(
+                            &mut particles,
+                            &defs,
+                            index,
+                            pos.as_vec2() + vec2(0.5, 0.),
+                            Vec2::ZERO,
+                            None,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct EmittersPlugin;
+impl Plugin for EmittersPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EmitterDefs>()
+            .init_resource::<VisualParticles>()
+            .add_event::<BurstEvent>()
+            .add_systems(OnEnter(GameState::Loading), emitters_setup)
+            .add_systems(
+                Update,
+                (
+                    update_emitters,
+                    update_visual_particles.after(update_emitters),
+                )
+                    .run_if(in_state(GameState::Game)),
+            )
+            .add_systems(
+                FixedUpdate,
+                surface_emitters
+                    .after(chunk_manager_update)
+                    .run_if(in_state(GameState::Game)),
+            );
+    }
+}
@@ -0,0 +1,402 @@
+use crate::prelude::*;
+
+/// Removes the rigidbody atoms in a circle, releasing them as particles of their materials.
+/// Used by the player tool and explosions.
+#[derive(Event)]
+pub struct CarveEvent {
+    /// Global position, with y going down like the atoms
+    pub center: Vec2,
+    pub radius: f32,
+    /// Speed the released atoms are thrown away from the center with
+    pub force: f32,
+    /// Entity the released atoms follow, like the player tool
+    pub follow: Option<Entity>,
+}
+
+/// Cuts the solid terrain atoms connected to the center, inside the radius, into a rigidbody.
+/// Used by the debug tool.
+#[derive(Event)]
+pub struct CutEvent {
+    /// Global position, with y going down like the atoms
+    pub center: Vec2,
+    /// Up to 127, so the rigidbody size fits in it's u8s
+    pub radius: f32,
+}
+
+/// A connected group of atoms of a rigidbody, on it's own grid
+struct Piece {
+    /// Top left of the piece on the rigidbody grid
+    offset: UVec2,
+    width: u8,
+    height: u8,
+    atoms: Vec<Atom>,
+    count: usize,
+    /// Center of the piece atoms on it's grid
+    centroid: Vec2,
+}
+
+/// Splits the rigidbody atoms in the groups connected by their sides
+fn pieces(rigidbody: &Rigidbody) -> Vec<Piece> {
+    let (width, height) = (rigidbody.width as i32, rigidbody.height as i32);
+    let index = |pos: IVec2| (pos.y * width + pos.x) as usize;
+
+    let mut seen = vec![false; rigidbody.atoms.len()];
+    let mut pieces = vec![];
+
+    for start in 0..rigidbody.atoms.len() {
+        if seen[start] || rigidbody.atoms[start].is_void() {
+            continue;
+        }
+        seen[start] = true;
+
+        let mut positions = vec![];
+        let mut stack = vec![ivec2(start as i32 % width, start as i32 / width)];
+        while let Some(pos) = stack.pop() {
+            positions.push(pos);
+
+            for off in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                let next = pos + off;
+                if next.x < 0 || next.y < 0 || next.x >= width || next.y >= height {
+                    continue;
+                }
+                if !seen[index(next)] && !rigidbody.atoms[index(next)].is_void() {
+                    seen[index(next)] = true;
+                    stack.push(next);
+                }
+            }
+        }
+
+        let min = positions.iter().copied().reduce(IVec2::min).unwrap();
+        let max = positions.iter().copied().reduce(IVec2::max).unwrap();
+        let size = max - min + 1;
+
+        let mut atoms = vec![Atom::default(); (size.x * size.y) as usize];
+        let mut centroid = Vec2::ZERO;
+        for pos in &positions {
+            let local = *pos - min;
+            atoms[(local.y * size.x + local.x) as usize] = rigidbody.atoms[index(*pos)];
+            centroid += local.as_vec2() + 0.5;
+        }
+
+        pieces.push(Piece {
+            offset: min.as_uvec2(),
+            width: size.x as u8,
+            height: size.y as u8,
+            atoms,
+            count: positions.len(),
+            centroid: centroid / positions.len() as f32,
+        });
+    }
+
+    pieces
+}
+
+pub fn carve_rigidbodies(
+    mut ev_carve: EventReader<CarveEvent>,
+    mut particles: ResMut<Particles>,
+    mut rigidbodies: Query<(&Transform, &mut Rigidbody)>,
+    materials: (Res<Assets<Materials>>, Res<MaterialsHandle>),
+) {
+    let materials = materials.0.get(&materials.1 .0).unwrap();
+
+    for event in ev_carve.read() {
+        for (transform, mut rigidbody) in &mut rigidbodies {
+            let (width, height) = (rigidbody.width as usize, rigidbody.height as usize);
+            let (top_left, angle) = Rigidbody::placement(transform);
+
+            // Center on the rigidbody grid
+            let center = (event.center - top_left).rotate(Vec2::from_angle(-angle));
+            let min = (center - event.radius).floor().max(Vec2::ZERO);
+            let max = (center + event.radius)
+                .ceil()
+                .min(vec2(width as f32 - 1., height as f32 - 1.));
+            if min.x > max.x || min.y > max.y {
+                continue;
+            }
+
+            for y in min.y as usize..=max.y as usize {
+                for x in min.x as usize..=max.x as usize {
+                    let atom = rigidbody.atoms[y * width + x];
+                    if atom.is_void() || vec2(x as f32, y as f32).distance(center) > event.radius {
+                        continue;
+                    }
+
+                    let pos = top_left + vec2(x as f32, y as f32).rotate(Vec2::from_angle(angle));
+                    let velocity = (pos - event.center).normalize_or_zero() * event.force;
+                    let mut particle = released_particle(atom, pos, velocity, materials);
+                    if let Some(follow) = event.follow {
+                        particle.state = PartState::Follow(follow);
+                    }
+                    particles.spawn(particle);
+
+                    rigidbody.atoms[y * width + x] = Atom::default();
+                    rigidbody.damaged = true;
+                }
+            }
+        }
+    }
+}
+
+/// Takes the cut atoms out of the chunks, keeping their colors on the new rigidbody
+pub fn cut_terrain(
+    mut commands: Commands,
+    mut ev_cut: EventReader<CutEvent>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    mut dirty_rects: ResMut<DirtyRects>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for event in ev_cut.read() {
+        let radius = event.radius.min(127.);
+        let is_cut = |chunk_manager: &ChunkManager, pos: IVec2| {
+            (pos.as_vec2() + 0.5).distance(event.center) <= radius
+                && chunk_manager
+                    .get_atom(&global_to_chunk(pos))
+                    .is_some_and(|atom| atom.is_solid())
+        };
+
+        // Flood fill from the atom under the center
+        let start = event.center.floor().as_ivec2();
+        if !is_cut(&chunk_manager, start) {
+            continue;
+        }
+        let mut positions = HashSet::from([start]);
+        let mut stack = vec![start];
+        while let Some(pos) = stack.pop() {
+            for off in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                let next = pos + off;
+                if !positions.contains(&next) && is_cut(&chunk_manager, next) {
+                    positions.insert(next);
+                    stack.push(next);
+                }
+            }
+        }
+        if positions.len() < MIN_PIECE_ATOMS {
+            continue;
+        }
+
+        let min = positions.iter().copied().reduce(IVec2::min).unwrap();
+        let max = positions.iter().copied().reduce(IVec2::max).unwrap();
+        let size = max - min + 1;
+
+        let mut atoms = vec![Atom::default(); (size.x * size.y) as usize];
+        for pos in &positions {
+            let local = *pos - min;
+            atoms[(local.y * size.x + local.x) as usize] =
+                *chunk_manager.get_atom(&global_to_chunk(*pos)).unwrap();
+        }
+
+        let Some(collider) = get_collider(&atom_values(&atoms), size.x as u32, size.y as u32)
+        else {
+            continue;
+        };
+
+        for pos in positions {
+            let chunk_pos = global_to_chunk(pos);
+            *chunk_manager.get_mut_atom(chunk_pos).unwrap() = Atom::default();
+            update_dirty_rects(&mut dirty_rects.render, chunk_pos);
+            update_dirty_rects_3x3(&mut dirty_rects.current, chunk_pos);
+        }
+
+        let rigidbody = Rigidbody {
+            atoms,
+            width: size.x as u8,
+            height: size.y as u8,
+            filled: vec![],
+            damaged: false,
+            displace: true,
+            resting: false,
+        };
+        let image = images.add(rigidbody_image(
+            &rigidbody.atoms,
+            rigidbody.width,
+            rigidbody.height,
+        ));
+
+        // Cut terrain has no definition, so it takes the defaults of one
+        commands.spawn((
+            collider,
+            rigidbody,
+            RapierRigidbody::Dynamic,
+            ColliderMassProperties::Density(default_density()),
+            Friction::coefficient(default_friction()),
+            Velocity::zero(),
+            ExternalImpulse::default(),
+            ExternalForce::default(),
+            Damping::default(),
+            Sleeping::default(),
+            ReadMassProperties::default(),
+            Sprite {
+                image,
+                anchor: bevy::sprite::Anchor::TopLeft,
+                ..Default::default()
+            },
+            Transform::from_xyz(min.x as f32, -min.y as f32, RIGIDBODY_LAYER),
+        ));
+    }
+}
+
+/// Rebuilds the image and collider of the damaged rigidbodies.
+/// Each disconnected piece becomes it's own rigidbody, moving like it's part of the body was.
+pub fn fracture_rigidbodies(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut rigidbodies: Query<(
+        Entity,
+        &mut Transform,
+        &mut Rigidbody,
+        &mut Sprite,
+        &Velocity,
+        &ReadMassProperties,
+        &ColliderMassProperties,
+        &Friction,
+    )>,
+    mut joints: Query<(Entity, &mut BodyJoint, &Parent)>,
+    mut particles: ResMut<Particles>,
+    materials: (Res<Assets<Materials>>, Res<MaterialsHandle>),
+) {
+    let materials = materials.0.get(&materials.1 .0).unwrap();
+
+    for (
+        entity,
+        mut transform,
+        mut rigidbody,
+        mut sprite,
+        velocity,
+        mass_prop,
+        density,
+        friction,
+    ) in &mut rigidbodies
+    {
+        if !rigidbody.damaged {
+            continue;
+        }
+
+        let (top_left, angle) = Rigidbody::placement(&transform);
+        let center_of_mass = transform
+            .transform_point(mass_prop.local_center_of_mass.extend(0.))
+            .xy();
+
+        // The biggest piece stays on the entity
+        let mut pieces = pieces(&rigidbody);
+        pieces.sort_by_key(|piece| std::cmp::Reverse(piece.count));
+        images.remove(&sprite.image);
+
+        let mut kept = false;
+        let mut kept_offset = Vec2::ZERO;
+        for piece in pieces {
+            let piece_top_left = top_left + piece.offset.as_vec2().rotate(Vec2::from_angle(angle));
+            let piece_transform = Transform {
+                translation: vec3(piece_top_left.x, -piece_top_left.y, RIGIDBODY_LAYER),
+                rotation: transform.rotation,
+                ..Default::default()
+            };
+            let body = Rigidbody {
+                atoms: piece.atoms,
+                width: piece.width,
+                height: piece.height,
+                filled: vec![],
+                damaged: false,
+                displace: rigidbody.displace,
+                resting: false,
+            };
+
+            let collider = get_collider(
+                &atom_values(&body.atoms),
+                body.width as u32,
+                body.height as u32,
+            );
+            let Some(collider) = collider.filter(|_| piece.count >= MIN_PIECE_ATOMS) else {
+                release_atoms(&mut particles, &piece_transform, &body, materials);
+                continue;
+            };
+
+            let center = piece_transform
+                .transform_point(vec3(piece.centroid.x, -piece.centroid.y, 0.))
+                .xy();
+            let piece_velocity = Velocity {
+                linvel: velocity.linear_velocity_at_point(center, center_of_mass),
+                angvel: velocity.angvel,
+            };
+            let image = images.add(rigidbody_image(&body.atoms, body.width, body.height));
+
+            if !kept {
+                kept = true;
+                kept_offset = piece.offset.as_vec2();
+                *transform = piece_transform;
+                *rigidbody = body;
+                sprite.image = image;
+                commands.entity(entity).insert((collider, piece_velocity));
+            } else {
+                commands.spawn((
+                    collider,
+                    body,
+                    RapierRigidbody::Dynamic,
+                    *density,
+                    *friction,
+                    piece_velocity,
+                    ExternalImpulse::default(),
+                    ExternalForce::default(),
+                    Damping::default(),
+                    Sleeping::default(),
+                    ReadMassProperties::default(),
+                    Sprite {
+                        image,
+                        anchor: bevy::sprite::Anchor::TopLeft,
+                        ..Default::default()
+                    },
+                    piece_transform,
+                ));
+            }
+        }
+
+        if !kept {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        // Moves the joints with the kept piece, the ones on the other pieces break
+        let size = ivec2(rigidbody.width as i32, rigidbody.height as i32);
+        for (joint_ent, mut joint, parent) in &mut joints {
+            let anchor = if parent.get() == entity {
+                joint.anchor
+            } else if joint.other == entity {
+                joint.other_anchor
+            } else {
+                continue;
+            };
+
+            let moved = anchor - kept_offset;
+            // Anchors on the right or bottom edge are on the last atoms
+            let pos = moved.floor().as_ivec2().min(size - 1);
+            let on_piece = pos.cmpge(IVec2::ZERO).all()
+                && !rigidbody.atoms[(pos.y * size.x + pos.x) as usize].is_void();
+
+            if !on_piece {
+                commands.entity(joint_ent).despawn_recursive();
+            } else if moved != anchor {
+                if parent.get() == entity {
+                    joint.anchor = moved;
+                } else {
+                    joint.other_anchor = moved;
+                }
+            }
+        }
+    }
+}
+
+pub struct FracturePlugin;
+impl Plugin for FracturePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CarveEvent>()
+            .add_event::<CutEvent>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    carve_rigidbodies.before(update_rigidibodies),
+                    cut_terrain.before(update_rigidibodies),
+                    fracture_rigidbodies.after(unfill_rigidbodies),
+                )
+                    .run_if(in_state(GameState::Game)),
+            );
+    }
+}
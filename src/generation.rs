@@ -0,0 +1,451 @@
+use noise::{
+    BasicMulti, Billow, Fbm, MultiFractal, NoiseFn, RidgedMulti, ScalePoint, SuperSimplex,
+};
+use smallvec::{smallvec, SmallVec};
+
+use crate::prelude::*;
+
+/// World generation settings, loaded from `assets/gen.ron`
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct GeneratorConfig {
+    pub seed: u32,
+    /// Divides global atom positions before sampling any noise
+    pub scale: f64,
+
+    /// Layers summed together with the y coordinate to shape the surface
+    #[serde(default)]
+    pub ground: Vec<NoiseLayer>,
+    /// Layers summed together to carve the caves, biomes can override it
+    #[serde(default)]
+    pub cave: Vec<NoiseLayer>,
+
+    /// Lower bound of the control value where the ground noise is used
+    pub air_limit: f64,
+
+    /// Low frequency layers deciding which biome is where, normalized to 0..1
+    #[serde(default)]
+    pub biome_noise: Vec<NoiseLayer>,
+    /// Width of the biome noise transition between two biomes
+    #[serde(default)]
+    pub biome_blend: f64,
+    /// Width, in atoms, of the depth transition between two biomes
+    #[serde(default)]
+    pub depth_blend: f64,
+    /// The first biome is the fallback, used wherever no other biome reaches.
+    /// Later biomes are placed on top of earlier ones.
+    #[serde(default)]
+    pub biomes: Vec<Biome>,
+
+    /// Placed in order after the base terrain
+    #[serde(default)]
+    pub deposits: Vec<Deposit>,
+    /// Grown in order on the terrain surfaces, after everything else
+    #[serde(default)]
+    pub decorations: Vec<Decoration>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Biome {
+    pub name: String,
+
+    /// Biome noise range where this biome appears
+    #[serde(default)]
+    pub min_noise: Option<f64>,
+    #[serde(default)]
+    pub max_noise: Option<f64>,
+    /// Depth range, in global atoms, where this biome appears
+    #[serde(default)]
+    pub min_depth: Option<f64>,
+    #[serde(default)]
+    pub max_depth: Option<f64>,
+
+    /// Control value where the ground layer turns into the cave layer
+    pub cave_start: f64,
+    /// How smoothly the ground and cave noises blend together
+    pub falloff: f64,
+    /// Replaces the config cave layers inside this biome
+    #[serde(default)]
+    pub cave: Option<Vec<NoiseLayer>>,
+
+    /// Materials of the ground layer, picked by the normalized noise value
+    #[serde(default)]
+    pub ground_bands: Vec<MaterialBand>,
+    /// Materials of the cave layer, picked by the normalized noise value
+    #[serde(default)]
+    pub cave_bands: Vec<MaterialBand>,
+    /// Background material of the cave layer, the ground layer uses it's own materials
+    #[serde(default)]
+    pub wall: Option<u8>,
+}
+
+impl Biome {
+    /// How much this biome is present at a biome noise value and depth, from 0 to 1
+    pub fn weight(&self, noise: f64, depth: f64, noise_blend: f64, depth_blend: f64) -> f64 {
+        ramp(self.min_noise.map(|min| noise - min), noise_blend)
+            * ramp(self.max_noise.map(|max| max - noise), noise_blend)
+            * ramp(self.min_depth.map(|min| depth - min), depth_blend)
+            * ramp(self.max_depth.map(|max| max - depth), depth_blend)
+    }
+}
+
+/// Goes from 0 to 1 as the distance crosses a edge, over the blend width
+fn ramp(distance: Option<f64>, blend: f64) -> f64 {
+    match distance {
+        None => 1.,
+        Some(distance) if blend <= 0. => (distance >= 0.) as u8 as f64,
+        Some(distance) => (distance / blend + 0.5).clamp(0., 1.),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum NoiseKind {
+    #[default]
+    BasicMulti,
+    Fbm,
+    Billow,
+    RidgedMulti,
+}
+
+/// A single multi fractal noise, every unset parameter keeps the noise crate default
+#[derive(Clone, Debug, Deserialize)]
+pub struct NoiseLayer {
+    #[serde(default)]
+    pub kind: NoiseKind,
+    /// Added to the world seed, so layers of the same kind don't look alike
+    #[serde(default)]
+    pub seed_offset: u32,
+
+    #[serde(default)]
+    pub octaves: Option<usize>,
+    #[serde(default)]
+    pub frequency: Option<f64>,
+    #[serde(default)]
+    pub lacunarity: Option<f64>,
+    #[serde(default)]
+    pub persistence: Option<f64>,
+    /// Only used by `RidgedMulti`
+    #[serde(default)]
+    pub attenuation: Option<f64>,
+
+    #[serde(default = "one")]
+    pub x_scale: f64,
+    #[serde(default = "one")]
+    pub y_scale: f64,
+    #[serde(default = "one")]
+    pub amplitude: f64,
+}
+
+fn one() -> f64 {
+    1.
+}
+
+/// A material used when the noise value falls inside `min..max`
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct MaterialBand {
+    /// If none the band has no lower limit
+    #[serde(default)]
+    pub min: Option<f64>,
+    /// If none the band has no upper limit
+    #[serde(default)]
+    pub max: Option<f64>,
+    pub id: u8,
+}
+
+impl MaterialBand {
+    pub fn contains(&self, value: f64) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value < max)
+    }
+
+    /// Gets the id of the first band containing the value, none means air
+    pub fn find(bands: &[MaterialBand], value: f64) -> Option<u8> {
+        bands
+            .iter()
+            .find(|band| band.contains(value))
+            .map(|band| band.id)
+    }
+}
+
+pub type BoxedNoise = Box<dyn NoiseFn<f64, 2> + Send + Sync>;
+
+fn multi_fractal<T: MultiFractal>(mut noise: T, layer: &NoiseLayer) -> T {
+    if let Some(octaves) = layer.octaves {
+        noise = noise.set_octaves(octaves);
+    }
+    if let Some(frequency) = layer.frequency {
+        noise = noise.set_frequency(frequency);
+    }
+    if let Some(lacunarity) = layer.lacunarity {
+        noise = noise.set_lacunarity(lacunarity);
+    }
+    if let Some(persistence) = layer.persistence {
+        noise = noise.set_persistence(persistence);
+    }
+
+    noise
+}
+
+impl NoiseLayer {
+    pub fn build(&self, seed: u32) -> BoxedNoise {
+        let seed = seed.wrapping_add(self.seed_offset);
+
+        let noise: BoxedNoise = match self.kind {
+            NoiseKind::BasicMulti => {
+                Box::new(multi_fractal(BasicMulti::<SuperSimplex>::new(seed), self))
+            }
+            NoiseKind::Fbm => Box::new(multi_fractal(Fbm::<SuperSimplex>::new(seed), self)),
+            NoiseKind::Billow => Box::new(multi_fractal(Billow::<SuperSimplex>::new(seed), self)),
+            NoiseKind::RidgedMulti => {
+                let mut noise = multi_fractal(RidgedMulti::<SuperSimplex>::new(seed), self);
+                if let Some(attenuation) = self.attenuation {
+                    noise = noise.set_attenuation(attenuation);
+                }
+                Box::new(noise)
+            }
+        };
+
+        Box::new(
+            ScalePoint::new(noise)
+                .set_x_scale(self.x_scale)
+                .set_y_scale(self.y_scale),
+        )
+    }
+}
+
+/// Sum of noise layers, each one multiplied by it's amplitude
+#[derive(Default)]
+pub struct LayeredNoise(pub Vec<(BoxedNoise, f64)>);
+
+impl LayeredNoise {
+    pub fn new(layers: &[NoiseLayer], seed: u32) -> Self {
+        Self(
+            layers
+                .iter()
+                .map(|layer| (layer.build(seed), layer.amplitude))
+                .collect(),
+        )
+    }
+}
+
+impl NoiseFn<f64, 2> for LayeredNoise {
+    fn get(&self, point: [f64; 2]) -> f64 {
+        self.0
+            .iter()
+            .map(|(noise, amplitude)| noise.get(point) * amplitude)
+            .sum()
+    }
+}
+
+/// The built noises from a `GeneratorConfig`, used to create new chunks
+#[derive(Default)]
+pub struct Generator {
+    pub config: GeneratorConfig,
+    pub ground: LayeredNoise,
+    pub cave: LayeredNoise,
+    pub biome: LayeredNoise,
+    /// Cave noise overrides, one for each biome
+    pub biome_caves: Vec<Option<LayeredNoise>>,
+    /// Loaded from `assets/structures`, set after the generator is created
+    pub structures: Vec<Structure>,
+    /// Run in order to generate each chunk
+    pub stages: Vec<Arc<dyn GenStage>>,
+}
+
+/// How much each biome is present at a position, only allocated with lots of biomes
+pub type BiomeWeights = SmallVec<[f64; 8]>;
+
+/// The result of sampling the generator at a position
+#[derive(Clone, Debug)]
+pub struct GenSample {
+    /// Noise value normalized to 0..1
+    pub value: f64,
+    /// If the sample is above the cave layer
+    pub ground: bool,
+    /// Index of the biome the materials are taken from
+    pub biome: usize,
+    /// How much of the cave noise is blended into the value, from 0 to 1
+    pub cave: f64,
+    /// How much each biome is present, kept for blending the caves
+    pub weights: BiomeWeights,
+}
+
+impl Generator {
+    pub fn new(config: GeneratorConfig) -> Self {
+        // Without a fallback biome every atom would be air
+        assert!(
+            !config.biomes.is_empty(),
+            "The generator config needs at least one biome"
+        );
+
+        let seed = config.seed;
+        Self {
+            ground: LayeredNoise::new(&config.ground, seed),
+            cave: LayeredNoise::new(&config.cave, seed),
+            biome: LayeredNoise::new(&config.biome_noise, seed),
+            biome_caves: config
+                .biomes
+                .iter()
+                .map(|biome| {
+                    biome
+                        .cave
+                        .as_ref()
+                        .map(|cave| LayeredNoise::new(cave, seed))
+                })
+                .collect(),
+            structures: vec![],
+            stages: GenPipeline::default().stages,
+            config,
+        }
+    }
+
+    pub fn load(path: &str) -> Self {
+        let bytes = std::fs::read(path).unwrap();
+        let config = ron::de::from_bytes::<GeneratorConfig>(&bytes).unwrap();
+
+        Self::new(config)
+    }
+
+    /// Gets how much each biome is present at a global atom position, adding up to 1
+    pub fn biome_weights(&self, pos: IVec2) -> BiomeWeights {
+        let config = &self.config;
+        let point = [pos.x as f64 / config.scale, pos.y as f64 / config.scale];
+        let noise = (self.biome.get(point) + 1.) / 2.;
+
+        let mut weights: BiomeWeights = smallvec![0.; config.biomes.len()];
+        let mut remaining = 1.;
+        for (i, biome) in config.biomes.iter().enumerate().skip(1).rev() {
+            let weight = biome.weight(noise, pos.y as f64, config.biome_blend, config.depth_blend);
+            weights[i] = weight * remaining;
+            remaining -= weights[i];
+        }
+        if let Some(fallback) = weights.first_mut() {
+            *fallback = remaining;
+        }
+
+        weights
+    }
+
+    /// Samples the terrain noise at a global atom position, with the caves
+    pub fn sample(&self, pos: IVec2) -> GenSample {
+        let mut sample = self.terrain(pos);
+        self.carve(pos, &mut sample);
+        sample
+    }
+
+    /// Samples the terrain shape at a global atom position, without the caves
+    pub fn terrain(&self, pos: IVec2) -> GenSample {
+        let config = &self.config;
+        let point = [pos.x as f64 / config.scale, pos.y as f64 / config.scale];
+        let weights = self.biome_weights(pos);
+
+        let (mut cave_start, mut falloff) = (0., 0.);
+        for (biome, weight) in config.biomes.iter().zip(&weights) {
+            cave_start += biome.cave_start * weight;
+            falloff += biome.falloff * weight;
+        }
+
+        let ground = self.ground.get(point) + point[1];
+        let control = ground - cave_start;
+
+        // Same as noise's Select, using the ground noise for control values within
+        // air_limit..cave_start and smoothly blending to the cave noise around it
+        let (lower, upper) = (config.air_limit, cave_start);
+        let cave = if control < lower - falloff || control >= upper + falloff {
+            1.
+        } else if control < lower + falloff {
+            1. - smooth((control - (lower - falloff)) / (2. * falloff))
+        } else if control < upper - falloff {
+            0.
+        } else {
+            smooth((control - (upper - falloff)) / (2. * falloff))
+        };
+
+        // Dithers the biome borders, so materials mix where biomes blend
+        let mut pick = hash(pos, config.seed);
+        let mut biome = 0;
+        for (i, weight) in weights.iter().enumerate() {
+            if pick < *weight {
+                biome = i;
+                break;
+            }
+            pick -= weight;
+        }
+
+        GenSample {
+            value: (ground + 1.) / 2.,
+            ground: (lower..(upper - falloff)).contains(&control),
+            biome,
+            cave,
+            weights,
+        }
+    }
+
+    /// Blends the cave noise into a terrain sample
+    pub fn carve(&self, pos: IVec2, sample: &mut GenSample) {
+        if sample.cave <= 0. {
+            return;
+        }
+
+        let config = &self.config;
+        let point = [pos.x as f64 / config.scale, pos.y as f64 / config.scale];
+
+        // Only evaluates the cave noises of the biomes present here
+        let mut shared = None;
+        let mut noise = 0.;
+        for (i, weight) in sample.weights.iter().enumerate().filter(|(_, w)| **w > 0.) {
+            noise += weight
+                * match &self.biome_caves[i] {
+                    Some(cave) => cave.get(point),
+                    None => *shared.get_or_insert_with(|| self.cave.get(point)),
+                };
+        }
+
+        sample.value = lerp(sample.value, (noise + 1.) / 2., sample.cave);
+    }
+
+    /// Gets the material id for a global atom position, none means air
+    pub fn material_at(&self, pos: IVec2) -> Option<u8> {
+        self.material_for(&self.sample(pos))
+    }
+
+    /// Gets the background material id for a terrain sample, none means there is no wall
+    pub fn wall_for(&self, sample: &GenSample, materials: &Materials) -> Option<u8> {
+        if sample.ground {
+            self.material_for(sample)
+                .filter(|id| !matches!(materials[*id].default_state, AtomState::Liquid))
+        } else {
+            self.config.biomes.get(sample.biome)?.wall
+        }
+    }
+
+    /// Gets the material id for a terrain sample from it's biome bands, none means air
+    pub fn material_for(&self, sample: &GenSample) -> Option<u8> {
+        let biome = self.config.biomes.get(sample.biome)?;
+
+        let bands = if sample.ground {
+            &biome.ground_bands
+        } else {
+            &biome.cave_bands
+        };
+
+        MaterialBand::find(bands, sample.value)
+    }
+}
+
+/// Deterministic value from 0 to 1 for a position and seed
+pub fn hash(pos: IVec2, seed: u32) -> f64 {
+    let mut x = (pos.x as u32 as u64) << 32 | pos.y as u32 as u64;
+    x ^= (seed as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+
+    (x >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+fn smooth(x: f64) -> f64 {
+    x * x * (3. - 2. * x)
+}
+
+fn lerp(a: f64, b: f64, alpha: f64) -> f64 {
+    a + (b - a) * alpha
+}
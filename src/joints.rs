@@ -0,0 +1,186 @@
+use crate::prelude::*;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum JointKind {
+    /// Rotates freely around the anchors, like a hinge
+    Revolute,
+    /// Keeps the bodies in the same place and angle from each other
+    Fixed,
+    /// Keeps the anchors up to `length` atoms apart, like a rope
+    Rope { length: f32 },
+}
+
+/// A joint from the rigidbody it's a child of to another rigidbody or a terrain anchor
+#[derive(Component, Clone, Debug)]
+pub struct BodyJoint {
+    pub kind: JointKind,
+    /// Anchor on the rigidbody atoms, with y going down
+    pub anchor: Vec2,
+    pub other: Entity,
+    /// Anchor on the other rigidbody atoms, zero for terrain anchors
+    pub other_anchor: Vec2,
+}
+
+impl BodyJoint {
+    pub fn impulse_joint(&self) -> ImpulseJoint {
+        // Rapier anchors have y going up
+        let anchor1 = vec2(self.other_anchor.x, -self.other_anchor.y);
+        let anchor2 = vec2(self.anchor.x, -self.anchor.y);
+
+        let joint: TypedJoint = match self.kind {
+            JointKind::Revolute => RevoluteJointBuilder::new()
+                .local_anchor1(anchor1)
+                .local_anchor2(anchor2)
+                .into(),
+            JointKind::Fixed => FixedJointBuilder::new()
+                .local_anchor1(anchor1)
+                .local_anchor2(anchor2)
+                .into(),
+            JointKind::Rope { length } => RopeJointBuilder::new(length)
+                .local_anchor1(anchor1)
+                .local_anchor2(anchor2)
+                .into(),
+        };
+
+        ImpulseJoint::new(self.other, joint)
+    }
+}
+
+/// A fixed point on a terrain atom for joints, they break when the atom stops being solid
+#[derive(Component)]
+pub struct TerrainAnchor(pub IVec2);
+
+/// Connects the rigidbody with the joint, returns the joint entity
+pub fn connect(commands: &mut Commands, body: Entity, joint: BodyJoint) -> Entity {
+    let ent = commands.spawn(joint).id();
+    commands.entity(body).add_child(ent);
+    ent
+}
+
+/// Spawns a anchor on the terrain atom at the global position, to connect rigidbodies to
+pub fn anchor_to_terrain(commands: &mut Commands, pos: IVec2) -> Entity {
+    commands
+        .spawn((
+            TerrainAnchor(pos),
+            RapierRigidbody::Fixed,
+            Transform::from_xyz(pos.x as f32 + 0.5, -(pos.y as f32 + 0.5), 0.),
+        ))
+        .id()
+}
+
+/// A joint from a structure, waiting for it's rigidbodies to be added
+#[derive(Component)]
+pub struct JointHandle {
+    pub joint: StructureJoint,
+    /// Global top left of the structure
+    pub origin: IVec2,
+    pub body: Entity,
+    /// The other rigidbody, none if it's anchored to the terrain
+    pub other: Option<Entity>,
+}
+
+pub fn add_joints(
+    mut commands: Commands,
+    handles: Query<(Entity, &JointHandle)>,
+    rigidbodies: Query<Entity, With<Rigidbody>>,
+    entities: Query<Entity>,
+) {
+    for (ent, handle) in &handles {
+        let bodies = [Some(handle.body), handle.other];
+        // The rigidbody failed to load, so there's nothing to connect
+        if bodies
+            .iter()
+            .flatten()
+            .any(|body| entities.get(*body).is_err())
+        {
+            commands.entity(ent).despawn();
+            continue;
+        }
+        if !bodies
+            .iter()
+            .flatten()
+            .all(|body| rigidbodies.contains(*body))
+        {
+            continue;
+        }
+
+        let def = &handle.joint;
+        let (other, other_anchor) = match (def.to, handle.other) {
+            (StructureJointTo::Body(_, anchor), Some(other)) => (other, anchor),
+            (StructureJointTo::Terrain(x, y), _) => {
+                let anchor = anchor_to_terrain(&mut commands, handle.origin + ivec2(x, y));
+                (anchor, (0, 0))
+            }
+            _ => unreachable!(),
+        };
+
+        connect(
+            &mut commands,
+            handle.body,
+            BodyJoint {
+                kind: def.kind,
+                anchor: vec2(def.anchor.0 as f32, def.anchor.1 as f32),
+                other,
+                other_anchor: vec2(other_anchor.0 as f32, other_anchor.1 as f32),
+            },
+        );
+        commands.entity(ent).despawn();
+    }
+}
+
+/// Builds the rapier joints of the new or changed joints, like when a rigidbody fractures
+pub fn update_joints(
+    mut commands: Commands,
+    joints: Query<(Entity, &BodyJoint), Changed<BodyJoint>>,
+) {
+    for (ent, joint) in &joints {
+        commands.entity(ent).try_insert(joint.impulse_joint());
+    }
+}
+
+/// Breaks the joints anchored to destroyed terrain atoms or missing rigidbodies
+pub fn break_joints(
+    mut commands: Commands,
+    chunk_manager: Res<ChunkManager>,
+    joints: Query<(Entity, &BodyJoint)>,
+    anchors: Query<(Entity, &TerrainAnchor)>,
+    entities: Query<Entity>,
+) {
+    for (ent, anchor) in &anchors {
+        let destroyed = chunk_manager
+            .get_atom(&global_to_chunk(anchor.0))
+            .is_some_and(|atom| !atom.is_solid());
+        let used = joints.iter().any(|(_, joint)| joint.other == ent);
+
+        if destroyed || !used {
+            commands.entity(ent).despawn();
+        }
+    }
+
+    for (ent, joint) in &joints {
+        let anchor_destroyed = anchors.get(joint.other).is_ok_and(|(_, anchor)| {
+            chunk_manager
+                .get_atom(&global_to_chunk(anchor.0))
+                .is_some_and(|atom| !atom.is_solid())
+        });
+
+        if anchor_destroyed || entities.get(joint.other).is_err() {
+            commands.entity(ent).despawn_recursive();
+        }
+    }
+}
+
+pub struct JointsPlugin;
+impl Plugin for JointsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                add_joints.after(add_rigidbodies),
+                update_joints.after(add_joints),
+                break_joints.after(update_joints),
+            )
+                .run_if(in_state(GameState::Game)),
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::VecDeque;
+
+use bevy::render::{render_asset::RenderAssetUsages, render_resource::*};
+use bevy::sprite::Anchor;
+
+use crate::prelude::*;
+
+/// Light level of each atom of a loaded chunk, drawn as a darkness overlay
+pub struct LightChunk {
+    pub light: Vec<u8>,
+    pub image: Handle<Image>,
+    pub entity: Entity,
+}
+
+/// Light field of the loaded chunks, with sunlight from the sky and emissive materials.
+/// Only the areas around changed atoms are relit each frame.
+#[derive(Resource, Default)]
+pub struct LightMap {
+    pub chunks: HashMap<IVec2, LightChunk>,
+    /// Global y of the first non void atom of each column, everything above it is sunlit
+    pub sun: HashMap<i32, i32>,
+    /// Top of the loaded area when the sun was computed
+    sun_top: i32,
+    /// Regions waiting to be relit, in global atoms
+    pending: Vec<IRect>,
+}
+
+impl LightMap {
+    pub fn light_at(&self, pos: IVec2) -> u8 {
+        let pos = global_to_chunk(pos);
+        self.chunks
+            .get(&pos.chunk)
+            .map_or(0, |chunk| chunk.light[pos.atom.d1()])
+    }
+
+    fn sunlit(&self, pos: IVec2) -> bool {
+        self.sun.get(&pos.x).is_some_and(|depth| pos.y < *depth)
+    }
+
+    /// Recomputes the sun depth of a column, queuing a relight where it changed
+    fn update_sun(&mut self, chunk_manager: &ChunkManager, x: i32) {
+        let top = chunk_manager.pos.y * CHUNK_LENGHT as i32;
+        let bottom = top + LOAD_HEIGHT * CHUNK_LENGHT as i32;
+
+        let mut depth = top;
+        if top < SUNLIGHT_DEPTH {
+            while depth < bottom {
+                match chunk_manager.get_atom(&global_to_chunk(ivec2(x, depth))) {
+                    Some(atom) if atom.is_void() => depth += 1,
+                    _ => break,
+                }
+            }
+        }
+
+        let old = self.sun.insert(x, depth).unwrap_or(top);
+        if old != depth {
+            let rect = IRect::new(x, old.min(depth), x, old.max(depth));
+            self.pending.push(rect.inflate(LIGHT_MAX as i32));
+        }
+    }
+
+    /// Floods the light inside the rect again, taking the light around it as it is
+    fn relight(&mut self, rect: IRect, chunk_manager: &ChunkManager, materials: &Materials) {
+        let size = rect.size() + 1;
+        let local = |pos: IVec2| {
+            let pos = pos - rect.min;
+            (pos.y * size.x + pos.x) as usize
+        };
+
+        let mut light = vec![0; (size.x * size.y) as usize];
+        let mut queue = VecDeque::new();
+
+        for y in rect.min.y..=rect.max.y {
+            for x in rect.min.x..=rect.max.x {
+                let pos = ivec2(x, y);
+                let Some(atom) = chunk_manager.get_atom(&global_to_chunk(pos)) else {
+                    continue;
+                };
+
+                let level = if self.sunlit(pos) {
+                    LIGHT_MAX
+                } else {
+                    materials[atom].emission.min(LIGHT_MAX)
+                };
+
+                if level > 0 {
+                    light[local(pos)] = level;
+                    queue.push_back((pos, level));
+                }
+            }
+        }
+
+        // Light coming from outside of the rect
+        for x in rect.min.x - 1..=rect.max.x + 1 {
+            for y in [rect.min.y - 1, rect.max.y + 1] {
+                queue.push_back((ivec2(x, y), self.light_at(ivec2(x, y))));
+            }
+        }
+        for y in rect.min.y..=rect.max.y {
+            for x in [rect.min.x - 1, rect.max.x + 1] {
+                queue.push_back((ivec2(x, y), self.light_at(ivec2(x, y))));
+            }
+        }
+
+        while let Some((pos, level)) = queue.pop_front() {
+            if level <= 1 {
+                continue;
+            }
+
+            for off in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                let next = pos + off;
+                if !rect.contains(next) {
+                    continue;
+                }
+                let Some(atom) = chunk_manager.get_atom(&global_to_chunk(next)) else {
+                    continue;
+                };
+
+                let loss = materials[atom].opacity.saturating_add(1);
+                let next_level = level.saturating_sub(loss);
+                if next_level > light[local(next)] {
+                    light[local(next)] = next_level;
+                    queue.push_back((next, next_level));
+                }
+            }
+        }
+
+        for y in rect.min.y..=rect.max.y {
+            for x in rect.min.x..=rect.max.x {
+                let pos = global_to_chunk(ivec2(x, y));
+                if let Some(chunk) = self.chunks.get_mut(&pos.chunk) {
+                    chunk.light[pos.atom.d1()] = light[local(ivec2(x, y))];
+                }
+            }
+        }
+    }
+}
+
+fn light_image() -> Image {
+    Image::new(
+        Extent3d {
+            height: CHUNK_LENGHT as u32,
+            width: CHUNK_LENGHT as u32,
+            ..Default::default()
+        },
+        TextureDimension::D2,
+        vec![0; CHUNK_LEN * 4],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+    )
+}
+
+/// Adds and removes the light chunks following the loaded chunks
+pub fn sync_light_chunks(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut light_map: ResMut<LightMap>,
+    chunk_manager: Res<ChunkManager>,
+) {
+    let removed: Vec<IVec2> = light_map
+        .chunks
+        .keys()
+        .filter(|index| !chunk_manager.chunks.contains_key(index))
+        .copied()
+        .collect();
+    for index in removed {
+        let chunk = light_map.chunks.remove(&index).unwrap();
+        commands.entity(chunk.entity).despawn();
+        images.remove(&chunk.image);
+    }
+
+    let mut columns = HashSet::new();
+    for index in chunk_manager.chunks.keys() {
+        if light_map.chunks.contains_key(index) {
+            continue;
+        }
+
+        let image = images.add(light_image());
+        let pos = *index * CHUNK_LENGHT as i32;
+        let entity = commands
+            .spawn((
+                Sprite {
+                    image: image.clone(),
+                    anchor: Anchor::TopLeft,
+                    ..Default::default()
+                },
+                Transform::from_xyz(pos.x as f32, -pos.y as f32, LIGHT_LAYER),
+            ))
+            .id();
+
+        light_map.chunks.insert(
+            *index,
+            LightChunk {
+                light: vec![0; CHUNK_LEN],
+                image,
+                entity,
+            },
+        );
+
+        let rect = IRect::from_corners(pos, pos + CHUNK_LENGHT as i32 - 1);
+        light_map.pending.push(rect.inflate(LIGHT_MAX as i32));
+        columns.extend(pos.x..pos.x + CHUNK_LENGHT as i32);
+    }
+
+    // The sunlight comes from the top of the loaded area, so it all changes when it moves
+    let top = chunk_manager.pos.y * CHUNK_LENGHT as i32;
+    if light_map.sun_top != top {
+        light_map.sun_top = top;
+        let left = chunk_manager.pos.x * CHUNK_LENGHT as i32;
+        columns.extend(left..left + LOAD_WIDTH * CHUNK_LENGHT as i32);
+    }
+
+    let loaded = chunk_manager.pos.x * CHUNK_LENGHT as i32
+        ..(chunk_manager.pos.x + LOAD_WIDTH) * CHUNK_LENGHT as i32;
+    light_map.sun.retain(|x, _| loaded.contains(x));
+    for x in columns {
+        light_map.update_sun(&chunk_manager, x);
+    }
+}
+
+/// Relights the areas around the atoms changed this frame, and updates the overlays
+pub fn update_lighting(
+    mut images: ResMut<Assets<Image>>,
+    mut light_map: ResMut<LightMap>,
+    chunk_manager: Res<ChunkManager>,
+    dirty_rects: Res<DirtyRects>,
+    materials: (Res<Assets<Materials>>, Res<MaterialsHandle>),
+) {
+    puffin::profile_function!();
+
+    let materials = materials.0.get(&materials.1 .0).unwrap();
+
+    for (index, rect) in &dirty_rects.render {
+        let pos = *index * CHUNK_LENGHT as i32;
+        let rect = IRect::from_corners(pos + rect.min.as_ivec2(), pos + rect.max.as_ivec2());
+
+        light_map.pending.push(rect.inflate(LIGHT_MAX as i32));
+        for x in rect.min.x..=rect.max.x {
+            // Changes below the first non void atom can't change the sunlight
+            if !matches!(light_map.sun.get(&x), Some(depth) if rect.min.y > *depth) {
+                light_map.update_sun(&chunk_manager, x);
+            }
+        }
+    }
+
+    // Merges overlapping regions, so the same atoms aren't relit twice
+    let mut merged: Vec<IRect> = vec![];
+    for mut rect in light_map.pending.drain(..) {
+        while let Some(i) = merged
+            .iter()
+            .position(|other| !other.intersect(rect).is_empty())
+        {
+            rect = rect.union(merged.swap_remove(i));
+        }
+        merged.push(rect);
+    }
+
+    let mut budget = LIGHT_BUDGET;
+    let mut changed = HashSet::new();
+    for rect in merged {
+        let area = ((rect.width() + 1) * (rect.height() + 1)) as usize;
+        if area > budget && budget < LIGHT_BUDGET {
+            light_map.pending.push(rect);
+            continue;
+        }
+        budget = budget.saturating_sub(area);
+
+        light_map.relight(rect, &chunk_manager, materials);
+
+        let min = global_to_chunk(rect.min).chunk;
+        let max = global_to_chunk(rect.max).chunk;
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                changed.insert(ivec2(x, y));
+            }
+        }
+    }
+
+    for index in changed {
+        let Some(chunk) = light_map.chunks.get(&index) else {
+            continue;
+        };
+        let Some(image) = images.get_mut(&chunk.image) else {
+            continue;
+        };
+
+        for (pixel, light) in image.data.chunks_exact_mut(4).zip(&chunk.light) {
+            let darkness = 255 - (*light as u32 * 255 / LIGHT_MAX as u32) as u8;
+            pixel.copy_from_slice(&[0, 0, 0, darkness]);
+        }
+    }
+}
+
+pub struct LightingPlugin;
+impl Plugin for LightingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LightMap>().add_systems(
+            PostUpdate,
+            (sync_light_chunks, update_lighting.after(sync_light_chunks))
+                .run_if(in_state(GameState::Game)),
+        );
+    }
+}
@@ -5,27 +5,45 @@ use bevy::prelude::*;
 mod actors;
 mod animation;
 mod atom;
+mod atom_colors;
 mod camera;
+mod capture;
 mod chunk;
+mod chunk_gen;
 mod chunk_group;
 mod chunk_manager;
 mod consts;
+mod creatures;
 mod debug;
+mod deposits;
+mod emitters;
+mod fracture;
+mod generation;
 mod geom_tools;
+mod joints;
+mod lighting;
 mod manager_api;
+mod map;
 mod materials;
 mod menu;
 mod particles;
+mod pathfinding;
+mod pipeline;
 mod player;
 mod player_inv;
+mod preview;
 mod puffin_plugin;
 mod rigidbody;
+mod structures;
 mod prelude {
     pub use crate::GameState;
     pub use crate::{
-        actors::*, animation::*, atom::*, camera::*, chunk::*, chunk_group::*, chunk_manager::*,
-        consts::*, debug::*, geom_tools::*, manager_api::*, materials::*, menu::*, particles::*,
-        player::*, player_inv::*, puffin_plugin::*, rigidbody::*,
+        actors::*, animation::*, atom::*, atom_colors::*, camera::*, capture::*, chunk::*,
+        chunk_gen::*, chunk_group::*, chunk_manager::*, consts::*, creatures::*, debug::*,
+        deposits::*, emitters::*, fracture::*, generation::*, geom_tools::*, joints::*,
+        lighting::*, manager_api::*, map::*, materials::*, menu::*, particles::*, pathfinding::*,
+        pipeline::*, player::*, player_inv::*, preview::*, puffin_plugin::*, rigidbody::*,
+        structures::*,
     };
     pub use bevy::input::mouse::MouseScrollUnit;
     pub use bevy::input::mouse::MouseWheel;
@@ -58,12 +76,18 @@ use prelude::*;
 fn main() {
     let args: Vec<_> = env::args().collect();
 
+    if args.get(1).is_some_and(|arg| arg == "preview") {
+        preview(&args[2..]);
+        return;
+    }
+
     let mut app = App::new();
 
     app.add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()))
         //local plugins
         .add_plugins((
             ChunkManagerPlugin,
+            ChunkGenPlugin,
             ActorsPlugin,
             PlayerPlugin,
             animation::AnimationPlugin,
@@ -72,10 +96,20 @@ fn main() {
             CameraPlugin,
             RigidbodyPlugin,
             PlayerInvPlugin,
+            StructuresPlugin,
+            LightingPlugin,
+            AtomColorsPlugin,
+            MapPlugin,
+            CapturePlugin,
+            FracturePlugin,
         ))
         .add_plugins((
             RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(1.),
             MenuPlugin,
+            JointsPlugin,
+            EmittersPlugin,
+            CreaturesPlugin,
+            PathfindingPlugin,
         ))
         .add_systems(Startup, setup)
         .init_state::<GameState>();
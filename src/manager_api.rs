@@ -11,6 +11,8 @@ pub struct UpdateChunksType<'a> {
     pub group: &'a mut ChunkGroup<'a>,
     pub dirty_update_rect_send: &'a Sender<DeferredDirtyRectUpdate>,
     pub dirty_render_rect_send: &'a Sender<DeferredDirtyRectUpdate>,
+    /// Particles ejected by impacts, spawned after the update
+    pub splash_send: &'a Sender<Particle>,
     pub materials: &'a Materials,
 }
 
@@ -192,6 +194,14 @@ pub fn _get_material(chunks: &UpdateChunksType, pos: IVec2) -> Material {
     chunks.materials.0[chunks.group[pos].id as usize]
 }
 
+/// Transforms a global 3x3 chunks pos to a chunk pos
+pub fn group_to_chunk_pos(chunks: &UpdateChunksType, pos: IVec2) -> ChunkPos {
+    let (local, idx) = global_to_local(pos);
+    let chunk = ChunkGroup::group_to_chunk(chunks.group.center_pos, idx);
+
+    ChunkPos::new(local.try_into().unwrap(), chunk)
+}
+
 /// Gets atom from a global pos
 pub fn get_atom(chunks: &UpdateChunksType, pos: IVec2) -> Atom {
     chunks.group[pos]
@@ -0,0 +1,454 @@
+use bevy::render::{render_asset::RenderAssetUsages, render_resource::*};
+
+use crate::prelude::*;
+
+/// Size of a chunk on the maps, in pixels
+pub const MAP_CHUNK_SIZE: usize = CHUNK_LENGHT / MAP_SCALE;
+/// Color of the empty space on the maps
+const MAP_VOID: [u8; 4] = [12, 12, 18, 220];
+
+/// Downsamples the chunk atoms to the map pixels, walls show darker behind empty space
+pub fn map_pixels(chunk: &Chunk) -> Vec<u8> {
+    let mut data = Vec::with_capacity(MAP_CHUNK_SIZE * MAP_CHUNK_SIZE * 4);
+    for py in 0..MAP_CHUNK_SIZE {
+        for px in 0..MAP_CHUNK_SIZE {
+            let (mut atoms, mut walls) = (([0; 3], 0), ([0; 3], 0));
+            for y in py * MAP_SCALE..(py + 1) * MAP_SCALE {
+                for x in px * MAP_SCALE..(px + 1) * MAP_SCALE {
+                    let i = y * CHUNK_LENGHT + x;
+                    let (sum, count, color): (&mut [u32; 3], &mut u32, [u8; 4]) =
+                        if !chunk.atoms[i].is_void() && !chunk.atoms[i].is_object() {
+                            (&mut atoms.0, &mut atoms.1, chunk.atoms[i].color)
+                        } else if !chunk.background[i].is_void() {
+                            (&mut walls.0, &mut walls.1, chunk.background[i].color)
+                        } else {
+                            continue;
+                        };
+
+                    for (sum, c) in sum.iter_mut().zip(color) {
+                        *sum += c as u32;
+                    }
+                    *count += 1;
+                }
+            }
+
+            // The pixel takes the atoms if they fill at least half of it
+            let color = if atoms.1 * 2 >= (MAP_SCALE * MAP_SCALE) as u32 {
+                let [r, g, b] = atoms.0.map(|c| (c / atoms.1) as u8);
+                [r, g, b, 255]
+            } else if walls.1 > 0 {
+                let [r, g, b] = walls.0.map(|c| (c / walls.1 / 5 * 2) as u8);
+                [r, g, b, 255]
+            } else {
+                MAP_VOID
+            };
+            data.extend_from_slice(&color);
+        }
+    }
+
+    data
+}
+
+/// Copies the chunk map pixels to the image, at the chunk position in chunks
+fn draw_chunk(image: &mut Image, pos: UVec2, pixels: &[u8]) {
+    let width = image.width() as usize;
+    for y in 0..MAP_CHUNK_SIZE {
+        let start =
+            ((pos.y as usize * MAP_CHUNK_SIZE + y) * width + pos.x as usize * MAP_CHUNK_SIZE) * 4;
+        let row = y * MAP_CHUNK_SIZE * 4;
+        image.data[start..start + MAP_CHUNK_SIZE * 4]
+            .copy_from_slice(&pixels[row..row + MAP_CHUNK_SIZE * 4]);
+    }
+}
+
+fn map_image(width: u32, height: u32) -> Image {
+    Image::new(
+        Extent3d {
+            width,
+            height,
+            ..Default::default()
+        },
+        TextureDimension::D2,
+        MAP_VOID.repeat(width as usize * height as usize),
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+    )
+}
+
+/// Chunks the player has been near, saved with the world
+#[derive(Resource, Default)]
+pub struct ExploredChunks(pub HashSet<IVec2>);
+
+impl Drop for ExploredChunks {
+    fn drop(&mut self) {
+        let file = File::create("assets/world/explored").unwrap();
+        let mut buffered = BufWriter::new(file);
+        bincode::serialize_into(&mut buffered, &self.0).unwrap();
+    }
+}
+
+/// Loaded area downsampled on the corner of the screen
+#[derive(Resource, Default)]
+pub struct Minimap {
+    image: Handle<Image>,
+    /// Manager position the image was drawn at
+    pos: IVec2,
+    drawn: HashSet<IVec2>,
+    /// Changed chunks waiting to be redrawn
+    dirty: HashSet<IVec2>,
+    frame: u32,
+}
+
+#[derive(Default)]
+enum MapState {
+    #[default]
+    Closed,
+    /// Waiting for the world to be saved, with the pixels of the loaded chunks
+    Waiting(HashMap<IVec2, Vec<u8>>),
+    /// Reading the rest of the explored chunks from the save
+    Loading(HashMap<IVec2, Vec<u8>>, Task<HashMap<IVec2, Vec<u8>>>),
+    Open,
+}
+
+/// Full screen map of the explored chunks
+#[derive(Resource, Default)]
+pub struct WorldMap {
+    state: MapState,
+    image: Handle<Image>,
+    /// First chunk on the image
+    min: IVec2,
+    /// Map pixel on the center of the screen
+    pan: Vec2,
+    zoom: f32,
+}
+
+#[derive(Component)]
+pub struct MinimapMarker;
+
+#[derive(Component)]
+pub struct WorldMapUi;
+
+#[derive(Component)]
+pub struct WorldMapImage;
+
+#[derive(Component)]
+pub struct WorldMapMarker;
+
+pub fn map_setup(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut minimap: ResMut<Minimap>,
+    mut world_map: ResMut<WorldMap>,
+) {
+    let explored = if let Ok(file) = File::open("assets/world/explored") {
+        let mut buffered = BufReader::new(file);
+        bincode::deserialize_from(&mut buffered).unwrap()
+    } else {
+        HashSet::new()
+    };
+    commands.insert_resource(ExploredChunks(explored));
+
+    let (width, height) = (
+        LOAD_WIDTH as u32 * MAP_CHUNK_SIZE as u32,
+        LOAD_HEIGHT as u32 * MAP_CHUNK_SIZE as u32,
+    );
+    *minimap = Minimap {
+        image: images.add(map_image(width, height)),
+        ..Default::default()
+    };
+    *world_map = WorldMap {
+        image: images.add(map_image(1, 1)),
+        zoom: 2.,
+        ..Default::default()
+    };
+
+    //Minimap
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                right: Val::Px(20.),
+                bottom: Val::Px(20.),
+                width: Val::Px(width as f32),
+                height: Val::Px(height as f32),
+                ..default()
+            },
+            ImageNode::new(minimap.image.clone()),
+            Outline {
+                width: Val::Px(6.),
+                offset: Val::Px(6.),
+                color: Color::WHITE,
+            },
+        ))
+        .with_child((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Px(4.),
+                height: Val::Px(4.),
+                ..default()
+            },
+            BackgroundColor(Color::WHITE),
+            MinimapMarker,
+        ));
+
+    //World map
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                overflow: Overflow::clip(),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0., 0., 0., 0.9)),
+            GlobalZIndex(10),
+            Visibility::Hidden,
+            WorldMapUi,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        position_type: PositionType::Absolute,
+                        ..default()
+                    },
+                    ImageNode::new(world_map.image.clone()),
+                    WorldMapImage,
+                ))
+                .with_child((
+                    Node {
+                        position_type: PositionType::Absolute,
+                        width: Val::Px(6.),
+                        height: Val::Px(6.),
+                        ..default()
+                    },
+                    BackgroundColor(Color::WHITE),
+                    WorldMapMarker,
+                ));
+        });
+}
+
+/// Marks the chunks around the player as explored
+pub fn update_explored(
+    mut explored: ResMut<ExploredChunks>,
+    chunk_manager: Res<ChunkManager>,
+    player: Query<&Actor, With<Player>>,
+) {
+    let Ok(actor) = player.get_single() else {
+        return;
+    };
+
+    let center = global_to_chunk(actor.pos).chunk;
+    for y in -MAP_EXPLORE_RADIUS..=MAP_EXPLORE_RADIUS {
+        for x in -MAP_EXPLORE_RADIUS..=MAP_EXPLORE_RADIUS {
+            let index = center + ivec2(x, y);
+            if chunk_manager.chunks.contains_key(&index) {
+                explored.0.insert(index);
+            }
+        }
+    }
+}
+
+/// Redraws the changed chunks on the minimap and moves the player marker
+pub fn update_minimap(
+    mut minimap: ResMut<Minimap>,
+    mut images: ResMut<Assets<Image>>,
+    chunk_manager: Res<ChunkManager>,
+    dirty_rects: Res<DirtyRects>,
+    player: Query<&Actor, With<Player>>,
+    mut marker: Query<&mut Node, With<MinimapMarker>>,
+) {
+    let minimap = &mut *minimap;
+
+    if let (Ok(actor), Ok(mut node)) = (player.get_single(), marker.get_single_mut()) {
+        let pos = (actor.pos - chunk_manager.pos * CHUNK_LENGHT as i32) / MAP_SCALE as i32;
+        node.left = Val::Px(pos.x as f32 - 2.);
+        node.top = Val::Px(pos.y as f32 - 2.);
+    }
+
+    if minimap.pos != chunk_manager.pos {
+        minimap.pos = chunk_manager.pos;
+        minimap.drawn.clear();
+        minimap.dirty.clear();
+        if let Some(image) = images.get_mut(&minimap.image) {
+            image.data = MAP_VOID.repeat(image.data.len() / 4);
+        }
+    }
+
+    minimap.dirty.extend(dirty_rects.render.keys());
+    minimap.frame += 1;
+
+    let mut to_draw: Vec<IVec2> = chunk_manager
+        .chunks
+        .keys()
+        .filter(|index| !minimap.drawn.contains(index))
+        .copied()
+        .collect();
+    // Changed chunks are only redrawn once in a while, as liquids are always moving
+    if minimap.frame % MINIMAP_INTERVAL == 0 {
+        to_draw.extend(minimap.dirty.drain());
+    }
+
+    if to_draw.is_empty() {
+        return;
+    }
+    let Some(image) = images.get_mut(&minimap.image) else {
+        return;
+    };
+
+    for index in to_draw {
+        let Some(chunk) = chunk_manager.chunks.get(&index) else {
+            continue;
+        };
+
+        draw_chunk(image, (index - minimap.pos).as_uvec2(), &map_pixels(chunk));
+        minimap.drawn.insert(index);
+    }
+}
+
+/// Opens the world map, reading the explored chunks that aren't loaded from the save
+pub fn update_world_map(
+    mut world_map: ResMut<WorldMap>,
+    mut images: ResMut<Assets<Image>>,
+    inputs: Res<Inputs>,
+    explored: Res<ExploredChunks>,
+    chunk_manager: Res<ChunkManager>,
+    saving_task: Res<SavingTask>,
+    player: Query<&Actor, With<Player>>,
+    mut map_ui: Query<&mut Visibility, With<WorldMapUi>>,
+) {
+    let world_map = &mut *world_map;
+
+    if inputs.map_toggle {
+        if matches!(world_map.state, MapState::Closed) {
+            let loaded = explored
+                .0
+                .iter()
+                .filter_map(|index| Some((*index, map_pixels(chunk_manager.chunks.get(index)?))))
+                .collect();
+            world_map.state = MapState::Waiting(loaded);
+        } else {
+            world_map.state = MapState::Closed;
+            *map_ui.single_mut() = Visibility::Hidden;
+        }
+    }
+
+    match &mut world_map.state {
+        MapState::Waiting(loaded) => {
+            if saving_task
+                .0
+                .as_ref()
+                .is_some_and(|task| !task.is_finished())
+            {
+                return;
+            }
+
+            let to_read: Vec<IVec2> = explored
+                .0
+                .iter()
+                .filter(|index| !loaded.contains_key(index))
+                .copied()
+                .collect();
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                let chunks = load_chunks();
+
+                to_read
+                    .into_iter()
+                    .filter_map(|index| Some((index, map_pixels(chunks.get(&index)?))))
+                    .collect()
+            });
+            world_map.state = MapState::Loading(std::mem::take(loaded), task);
+        }
+        MapState::Loading(loaded, task) => {
+            if !task.is_finished() {
+                return;
+            }
+
+            let mut pixels = block_on(task);
+            pixels.extend(loaded.drain());
+
+            let min = pixels
+                .keys()
+                .copied()
+                .reduce(IVec2::min)
+                .unwrap_or_default();
+            let max = pixels
+                .keys()
+                .copied()
+                .reduce(IVec2::max)
+                .unwrap_or_default();
+            let size = (max - min + 1).as_uvec2() * MAP_CHUNK_SIZE as u32;
+
+            let mut image = map_image(size.x, size.y);
+            for (index, pixels) in pixels {
+                draw_chunk(&mut image, (index - min).as_uvec2(), &pixels);
+            }
+            images.insert(&world_map.image, image);
+            world_map.min = min;
+
+            if let Ok(actor) = player.get_single() {
+                world_map.pan =
+                    ((actor.pos - min * CHUNK_LENGHT as i32) / MAP_SCALE as i32).as_vec2();
+            }
+            world_map.state = MapState::Open;
+            *map_ui.single_mut() = Visibility::Visible;
+        }
+        MapState::Closed | MapState::Open => {}
+    }
+}
+
+/// Pans and zooms the open world map
+pub fn move_world_map(
+    mut world_map: ResMut<WorldMap>,
+    images: Res<Assets<Image>>,
+    inputs: Res<Inputs>,
+    window: Query<&Window>,
+    player: Query<&Actor, With<Player>>,
+    mut map_image: Query<&mut Node, (With<WorldMapImage>, Without<WorldMapMarker>)>,
+    mut marker: Query<&mut Node, (With<WorldMapMarker>, Without<WorldMapImage>)>,
+) {
+    if !matches!(world_map.state, MapState::Open) {
+        return;
+    }
+    let (Ok(window), Some(image)) = (window.get_single(), images.get(&world_map.image)) else {
+        return;
+    };
+
+    world_map.zoom = (world_map.zoom * 1.25_f32.powf(inputs.map_zoom))
+        .clamp(MAP_ZOOM_LOWER_BOUND, MAP_ZOOM_UPPER_BOUND);
+    let zoom = world_map.zoom;
+    world_map.pan += inputs.map_pan * MAP_PAN_SPEED / zoom;
+
+    let mut node = map_image.single_mut();
+    node.width = Val::Px(image.width() as f32 * zoom);
+    node.height = Val::Px(image.height() as f32 * zoom);
+    node.left = Val::Px(window.width() / 2. - world_map.pan.x * zoom);
+    node.top = Val::Px(window.height() / 2. - world_map.pan.y * zoom);
+
+    if let Ok(actor) = player.get_single() {
+        let pos = (actor.pos - world_map.min * CHUNK_LENGHT as i32).as_vec2() / MAP_SCALE as f32;
+        let mut node = marker.single_mut();
+        node.left = Val::Px(pos.x * zoom - 3.);
+        node.top = Val::Px(pos.y * zoom - 3.);
+    }
+}
+
+pub struct MapPlugin;
+impl Plugin for MapPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Minimap>()
+            .init_resource::<WorldMap>()
+            .add_systems(OnEnter(GameState::Game), map_setup)
+            .add_systems(
+                Update,
+                (
+                    update_explored,
+                    update_minimap,
+                    update_world_map,
+                    move_world_map.after(update_world_map),
+                )
+                    .run_if(in_state(GameState::Game)),
+            );
+    }
+}
@@ -18,9 +18,23 @@ pub struct Material {
     pub damage: f32,
     #[serde(default)]
     pub default_state: AtomState,
+    /// Light level the material gives, up to `LIGHT_MAX`
+    #[serde(default)]
+    pub emission: u8,
+    /// Light lost when going through the material, on top of the 1 lost on every atom
+    #[serde(default)]
+    pub opacity: u8,
+    #[serde(default)]
+    pub animation: ColorAnimation,
+    /// Density of liquids, rigidbodies less dense than it float if it doesn't damage them
+    #[serde(default)]
+    pub density: f32,
+    /// Atoms splashed on fast impacts, for each atom per tick of speed over `SPLASH_MIN_SPEED`
+    #[serde(default)]
+    pub splash: f32,
 }
 
-#[derive(Asset, TypePath, Debug, Deserialize, Default)]
+#[derive(Asset, TypePath, Clone, Debug, Deserialize, Default)]
 pub struct Materials(pub Vec<Material>);
 
 impl Materials {
@@ -49,6 +63,25 @@ impl std::ops::Index<u8> for Materials {
     }
 }
 
+/// Maps image colors to material ids, each pixel takes the id of the closest color
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ColorMapping(pub Vec<([u8; 3], u8)>);
+
+impl ColorMapping {
+    pub fn get(&self, color: [u8; 4]) -> Option<u8> {
+        self.0
+            .iter()
+            .min_by_key(|(mapped, _)| {
+                mapped
+                    .iter()
+                    .zip(color)
+                    .map(|(a, b)| (*a as i32 - b as i32).pow(2))
+                    .sum::<i32>()
+            })
+            .map(|(_, id)| *id)
+    }
+}
+
 //Asset stuff
 
 #[derive(Resource, Default)]
@@ -97,9 +130,14 @@ pub fn materials_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
 pub fn materials_wait(
     asset_server: Res<AssetServer>,
     materials: Res<MaterialsHandle>,
+    assets: (Res<StructureAssets>, Res<RigidbodyAssets>),
     mut next_state: ResMut<NextState<GameState>>,
 ) {
-    if asset_server.is_loaded(&materials.0) {
+    let (structures, rigidbodies) = assets;
+    if asset_server.is_loaded(&materials.0)
+        && structures.is_loaded(&asset_server)
+        && rigidbodies.is_loaded(&asset_server)
+    {
         next_state.set(GameState::Game);
     }
 }
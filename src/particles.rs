@@ -1,17 +1,23 @@
+use bevy::render::{
+    mesh::{Indices, PrimitiveTopology},
+    render_asset::RenderAssetUsages,
+    view::NoFrustumCulling,
+};
+
 use crate::prelude::*;
 
-//To spawn a particle just add a entity with this component
-#[derive(Component, Debug, Default)]
+//To spawn a particle just push it to the `Particles` pool
+#[derive(Debug, Default, Clone)]
 pub struct Particle {
     pub atom: Atom,
     pub velocity: Vec2,
-    //Used when initiating particle
+    //Global position, with y going down like the atoms
     pub pos: Vec2,
     pub state: PartState,
 }
 
 /// Particle State
-#[derive(Default, Debug, PartialEq)]
+#[derive(Default, Debug, PartialEq, Clone, Copy)]
 pub enum PartState {
     //Used when the particle is looking for a place to put itself
     Looking,
@@ -21,225 +27,281 @@ pub enum PartState {
     Follow(Entity),
 }
 
-#[derive(Component)]
-pub struct Hydrated;
+/// All the particles, simulated in bulk and drawn on a single mesh
+#[derive(Resource, Default)]
+pub struct Particles {
+    pub pool: Vec<Particle>,
+    mesh: Handle<Mesh>,
+}
 
-pub fn hydrate_particles(
-    mut commands: Commands,
-    particles: Query<(&Particle, Entity), Without<Hydrated>>,
-) {
-    //Spawn particle sprite
-    for (particle, ent) in particles.iter() {
-        let mult = if particle.state == PartState::Normal {
-            1.2
-        } else {
-            0.8
-        };
-        commands
-            .entity(ent)
-            .insert((
-                Sprite {
-                    color: Color::srgba(
-                        particle.atom.color[0] as f32 / 255. * mult,
-                        particle.atom.color[1] as f32 / 255. * mult,
-                        particle.atom.color[2] as f32 / 255. * mult,
-                        particle.atom.color[3] as f32 / 255.,
-                    ),
-                    custom_size: Some(Vec2::new(1.0, 1.0)),
-                    ..default()
-                },
-                Transform::from_translation(Vec3::new(
-                    particle.pos.x,
-                    -particle.pos.y,
-                    PARTICLE_LAYER,
-                )),
-            ))
-            .insert(Hydrated);
+impl Particles {
+    pub fn spawn(&mut self, particle: Particle) {
+        self.pool.push(particle);
+    }
+}
+
+/// What happens to a particle after it moves
+enum ParticleStep {
+    Keep,
+    /// Puts the atom on the chunks
+    Place(ChunkPos),
+    /// Gives the atom to the player
+    Collect,
+}
+
+fn step_particle(
+    particle: &mut Particle,
+    chunk_manager: &ChunkManager,
+    targets: &HashMap<Entity, Vec2>,
+    bounds: (IVec2, IVec2),
+) -> ParticleStep {
+    let cur_pos = particle.pos;
+    let dest_pos = cur_pos + particle.velocity;
+
+    //If not on bounds, remove particle
+    let in_bounds = |pos: Vec2| {
+        let pos = pos.as_ivec2();
+        pos.cmpge(bounds.0).all() && pos.cmplt(bounds.1).all()
+    };
+    if !in_bounds(cur_pos) || !in_bounds(dest_pos) {
+        return ParticleStep::Collect;
+    }
+
+    match particle.state {
+        PartState::Looking | PartState::Normal => {
+            if particle.state == PartState::Normal {
+                particle.velocity += GRAVITY as f32 * Vec2::Y;
+            }
+
+            let mut step = ParticleStep::Keep;
+            let mut prev_pos = cur_pos.as_ivec2();
+            for pos in Line::new(cur_pos.as_ivec2(), (dest_pos - cur_pos).as_ivec2()) {
+                //Chunks still generating show up in a few frames, so the particle waits for them
+                let (Some(atom), Some(prev_atom)) = (
+                    chunk_manager.get_atom(&global_to_chunk(pos)),
+                    chunk_manager.get_atom(&global_to_chunk(prev_pos)),
+                ) else {
+                    return ParticleStep::Keep;
+                };
+
+                if particle.state == PartState::Normal && !atom.is_void() {
+                    //Hit something!
+                    //If our previous pos is free
+                    if prev_atom.is_void() {
+                        step = ParticleStep::Place(global_to_chunk(prev_pos));
+                    } else {
+                        //Upward warp if can't find a place to put
+                        particle.velocity = vec2(0., -2.);
+                        particle.state = PartState::Looking;
+                    }
+
+                    break;
+                } else if particle.state == PartState::Looking && prev_atom.is_void() {
+                    step = ParticleStep::Place(global_to_chunk(pos));
+                    break;
+                }
+
+                prev_pos = pos;
+            }
+
+            particle.pos = dest_pos;
+            step
+        }
+        PartState::Follow(follow_ent) => {
+            let Some(&follow_pos) = targets.get(&follow_ent) else {
+                return ParticleStep::Collect;
+            };
+
+            let mag = (particle.velocity.length()).clamp(0., 6.);
+            let angle = follow_pos - cur_pos;
+            let angle = angle.y.atan2(angle.x);
+
+            particle.velocity = vec2(angle.cos(), angle.sin()) * (mag + 0.5);
+            particle.pos += particle.velocity;
+
+            if particle.pos.distance(follow_pos) < 3. {
+                ParticleStep::Collect
+            } else {
+                ParticleStep::Keep
+            }
+        }
     }
 }
 
 pub fn update_particles(
-    mut commands: Commands,
-    mut particles: Query<(&mut Particle, &mut Transform, Entity), With<Hydrated>>,
-    entities: Query<&GlobalTransform, Without<Particle>>,
+    mut particles: ResMut<Particles>,
+    entities: Query<&GlobalTransform>,
     mut chunk_manager: ResMut<ChunkManager>,
     mut dirty_rects: ResMut<DirtyRects>,
     mut ev_item: EventWriter<ItemEvent>,
+    mut ev_burst: EventWriter<BurstEvent>,
 ) {
-    let compute_pool = ComputeTaskPool::get();
-
-    compute_pool.scope(|deferred_scope| {
-        let manager_pos = &chunk_manager.pos.clone();
-        let chunk_manager = Arc::new(RwLock::new(&mut chunk_manager));
-
-        let (particles_send, particles_recv) = async_channel::unbounded::<DeferredParticleUpdate>();
-        let particle_send = &particles_send;
-
-        let chunk_manager_deffered = Arc::clone(&chunk_manager);
-        deferred_scope.spawn(async move {
-            while let Ok(update) = particles_recv.recv().await {
-                if let Some(chunk_pos) = update.remove.0 {
-                    let mut change_atom = false;
-                    if let Some(atom) = chunk_manager_deffered.read().unwrap().get_atom(&chunk_pos)
-                    {
-                        if atom.is_void() {
-                            change_atom = true;
-                        }
-                    }
+    puffin::profile_function!();
 
-                    if change_atom {
-                        let atom = &mut chunk_manager_deffered.write().unwrap()[chunk_pos];
-                        *atom = update.remove.1;
-                        commands.entity(update.ent).despawn();
+    //Positions of the followed entities, with y going down
+    let targets: HashMap<Entity, Vec2> = particles
+        .pool
+        .iter()
+        .filter_map(|particle| match particle.state {
+            PartState::Follow(ent) => Some(ent),
+            _ => None,
+        })
+        .filter_map(|ent| {
+            let pos = entities.get(ent).ok()?.translation().xy();
+            Some((ent, vec2(pos.x, -pos.y)))
+        })
+        .collect();
 
-                        update_dirty_rects(&mut dirty_rects.render, chunk_pos);
-                        update_dirty_rects_3x3(&mut dirty_rects.current, chunk_pos);
-                    }
-                } else {
-                    ev_item.send(ItemEvent::AddOne(Item::Atom(update.remove.1)));
-                    commands.entity(update.ent).despawn();
-                    continue;
+    let min = chunk_manager.pos * CHUNK_LENGHT as i32;
+    let bounds = (
+        min,
+        min + ivec2(LOAD_WIDTH, LOAD_HEIGHT) * CHUNK_LENGHT as i32,
+    );
+
+    //Moves the particles in parallel, then applies what they did in order
+    let manager = &*chunk_manager;
+    let steps =
+        particles
+            .pool
+            .par_chunk_map_mut(ComputeTaskPool::get(), PARTICLE_BATCH, |_, batch| {
+                batch
+                    .iter_mut()
+                    .map(|particle| step_particle(particle, manager, &targets, bounds))
+                    .collect::<Vec<_>>()
+            });
+
+    let mut steps = steps.into_iter().flatten();
+    particles
+        .pool
+        .retain(|particle| match steps.next().unwrap() {
+            ParticleStep::Keep => true,
+            ParticleStep::Place(chunk_pos) => {
+                let Some(atom) = chunk_manager.get_mut_atom(chunk_pos) else {
+                    return true;
+                };
+                //Another particle got there first
+                if !atom.is_void() {
+                    return true;
                 }
+
+                *atom = particle.atom;
+                update_dirty_rects(&mut dirty_rects.render, chunk_pos);
+                update_dirty_rects_3x3(&mut dirty_rects.current, chunk_pos);
+
+                //Powders make dust when they land
+                if particle.atom.is_powder() {
+                    ev_burst.send(BurstEvent {
+                        tint: Some(particle.atom.color),
+                        ..BurstEvent::new("dust", chunk_pos.to_global().as_vec2() + 0.5)
+                    });
+                }
+                false
+            }
+            ParticleStep::Collect => {
+                ev_item.send(ItemEvent::AddOne(Item::Atom(particle.atom)));
+                false
             }
         });
+}
 
-        particles
-            .par_iter_mut()
-            .for_each(|(mut particle, mut transform, ent)| {
-                let mut dest_pos = transform.translation.xy();
-                dest_pos.y *= -1.;
-                dest_pos += particle.velocity;
-
-                let mut cur_pos = transform.translation.xy();
-                cur_pos.y *= -1.;
-
-                let x_bound = (manager_pos.x * CHUNK_LENGHT as i32)
-                    ..((manager_pos.x + LOAD_WIDTH) * CHUNK_LENGHT as i32);
-                let y_bound = (manager_pos.y * CHUNK_LENGHT as i32)
-                    ..((manager_pos.y + LOAD_HEIGHT) * CHUNK_LENGHT as i32);
-                //If not on bounds, remove particle
-                if !x_bound.contains(&(dest_pos.x as i32))
-                    || !y_bound.contains(&(dest_pos.y as i32))
-                    || !x_bound.contains(&(cur_pos.x as i32))
-                    || !y_bound.contains(&(cur_pos.y as i32))
-                {
-                    particle_send
-                        .try_send(DeferredParticleUpdate {
-                            remove: (None, particle.atom),
-                            ent,
-                        })
-                        .unwrap();
-                } else {
-                    match particle.state {
-                        PartState::Looking | PartState::Normal => {
-                            if particle.state == PartState::Normal {
-                                particle.velocity += GRAVITY as f32 * Vec2::Y;
-                            }
-
-                            let mut prev_pos = cur_pos.as_ivec2();
-                            for pos in
-                                Line::new(cur_pos.as_ivec2(), (dest_pos - cur_pos).as_ivec2())
-                            {
-                                let chunk_pos = global_to_chunk(pos);
-                                let prev_chunk_pos = global_to_chunk(prev_pos);
-
-                                let atom =
-                                    *chunk_manager.read().unwrap().get_atom(&chunk_pos).unwrap();
-                                let prev_atom = *chunk_manager
-                                    .read()
-                                    .unwrap()
-                                    .get_atom(&prev_chunk_pos)
-                                    .unwrap();
-
-                                if particle.state == PartState::Normal && !atom.is_void() {
-                                    //Hit something!
-                                    //If our previous pos is free
-                                    if prev_atom.is_void() {
-                                        particle_send
-                                            .try_send(DeferredParticleUpdate {
-                                                remove: (
-                                                    Some(global_to_chunk(prev_pos)),
-                                                    particle.atom,
-                                                ),
-                                                ent,
-                                            })
-                                            .unwrap();
-                                    } else if particle.state != PartState::Looking {
-                                        //Upward warp if can't find a place to put
-                                        particle.velocity.y = -2.;
-                                        particle.velocity.x = 0.;
-                                        particle.state = PartState::Looking;
-                                    }
-
-                                    break;
-                                } else if particle.state == PartState::Looking
-                                    && prev_atom.is_void()
-                                {
-                                    particle_send
-                                        .try_send(DeferredParticleUpdate {
-                                            remove: (Some(global_to_chunk(pos)), particle.atom),
-                                            ent,
-                                        })
-                                        .unwrap();
-                                    break;
-                                }
-
-                                prev_pos = pos;
-                            }
-                            transform.translation.x = dest_pos.x;
-                            transform.translation.y = -dest_pos.y;
-                        }
-                        PartState::Follow(follow_ent) => {
-                            let follow_transform = entities.get(follow_ent).unwrap();
-                            let mut follow_pos =
-                                follow_transform.compute_transform().translation.xy();
-                            follow_pos.y *= -1.;
-
-                            let mag = (particle.velocity.length()).clamp(0., 6.);
-                            let angle = follow_pos - cur_pos;
-                            let angle = angle.y.atan2(angle.x);
-
-                            particle.velocity = vec2(angle.cos(), angle.sin()) * (mag + 0.5);
-
-                            let mut part_vel = particle.velocity;
-                            part_vel.y *= -1.;
-                            transform.translation += part_vel.extend(0.);
-
-                            if transform
-                                .translation
-                                .xy()
-                                .distance(follow_transform.compute_transform().translation.xy())
-                                < 3.
-                            {
-                                particle_send
-                                    .try_send(DeferredParticleUpdate {
-                                        remove: (None, particle.atom),
-                                        ent,
-                                    })
-                                    .unwrap();
-                            }
-                        }
-                    }
-                }
-            });
-    });
+pub fn particles_setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let mesh = meshes.add(
+        Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, Vec::<[f32; 3]>::new())
+        .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, Vec::<[f32; 4]>::new())
+        .with_inserted_indices(Indices::U32(vec![])),
+    );
+
+    commands.spawn((
+        Name::new("Particles"),
+        Mesh2d(mesh.clone()),
+        MeshMaterial2d(materials.add(ColorMaterial::default())),
+        Transform::from_xyz(0., 0., PARTICLE_LAYER),
+        // The mesh changes every frame, so it's bounds would be outdated
+        NoFrustumCulling,
+    ));
+
+    commands.insert_resource(Particles { pool: vec![], mesh });
 }
 
-pub struct DeferredParticleUpdate {
-    pub ent: Entity,
-    pub remove: (Option<ChunkPos>, Atom),
+/// Rebuilds the particles mesh, a square for each atom and visual particle
+pub fn draw_particles(
+    particles: Res<Particles>,
+    visual_particles: Res<VisualParticles>,
+    emitters: Res<EmitterDefs>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let Some(mesh) = meshes.get_mut(&particles.mesh) else {
+        return;
+    };
+
+    let count = particles.pool.len() + visual_particles.0.len();
+    let mut positions = Vec::with_capacity(count * 4);
+    let mut colors = Vec::with_capacity(count * 4);
+    let mut indices = Vec::with_capacity(count * 6);
+
+    let mut square = |pos: Vec2, size: f32, color: Color| {
+        let i = positions.len() as u32;
+        let half = size / 2.;
+        for corner in [vec2(-1., -1.), vec2(1., -1.), vec2(1., 1.), vec2(-1., 1.)] {
+            let vertex = pos + corner * half;
+            positions.push([vertex.x, -vertex.y, 0.]);
+            colors.push(color.to_linear().to_f32_array());
+        }
+        indices.extend([i, i + 1, i + 2, i, i + 2, i + 3]);
+    };
+
+    for particle in &particles.pool {
+        let mult = if particle.state == PartState::Normal {
+            1.2
+        } else {
+            0.8
+        };
+        let [r, g, b, a] = particle.atom.color.map(|c| c as f32 / 255.);
+        square(
+            particle.pos,
+            1.,
+            Color::srgba(r * mult, g * mult, b * mult, a),
+        );
+    }
+
+    for particle in &visual_particles.0 {
+        let def = &emitters.defs[particle.emitter];
+        square(
+            particle.pos,
+            def.size,
+            def.color(particle.age / particle.lifetime, particle.tint),
+        );
+    }
+
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.insert_indices(Indices::U32(indices));
 }
 
 pub struct ParticlesPlugin;
 impl Plugin for ParticlesPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            FixedUpdate,
-            (
-                hydrate_particles.after(update_player),
-                update_particles.before(chunk_manager_update),
+        app.init_resource::<Particles>()
+            .add_systems(OnEnter(GameState::Game), particles_setup)
+            .add_systems(
+                FixedUpdate,
+                update_particles
+                    .before(chunk_manager_update)
+                    .run_if(in_state(GameState::Game)),
             )
-                .run_if(in_state(GameState::Game)),
-        );
+            .add_systems(
+                Update,
+                draw_particles
+                    .after(update_visual_particles)
+                    .run_if(in_state(GameState::Game)),
+            );
     }
 }
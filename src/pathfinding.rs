@@ -0,0 +1,404 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use itertools::Itertools;
+
+use crate::prelude::*;
+
+// Each chunk row is stored on a single u64
+const _: () = assert!(CHUNK_LENGHT == 64);
+
+/// Atoms actors can't go through, a bit for each atom of the loaded chunks
+#[derive(Clone, Default)]
+pub struct NavGrid {
+    chunks: HashMap<IVec2, Vec<u64>>,
+}
+
+impl NavGrid {
+    /// If the span of atoms on a global row is free, unloaded atoms aren't
+    fn row_free(&self, mut x: i32, y: i32, width: i32) -> bool {
+        let end = x + width;
+        while x < end {
+            let chunk_pos = global_to_chunk(ivec2(x, y));
+            let Some(rows) = self.chunks.get(&chunk_pos.chunk) else {
+                return false;
+            };
+
+            let start = chunk_pos.atom.x as i32;
+            let span = (CHUNK_LENGHT as i32 - start).min(end - x);
+            let bits = if span == CHUNK_LENGHT as i32 {
+                u64::MAX
+            } else {
+                ((1_u64 << span) - 1) << start
+            };
+            if rows[chunk_pos.atom.y as usize] & bits != 0 {
+                return false;
+            }
+
+            x += span;
+        }
+
+        true
+    }
+
+    /// If a actor of the size fits with it's top left on the position
+    pub fn fits(&self, pos: IVec2, size: IVec2) -> bool {
+        (pos.y..pos.y + size.y).all(|y| self.row_free(pos.x, y, size.x))
+    }
+
+    pub fn on_ground(&self, pos: IVec2, size: IVec2) -> bool {
+        !self.row_free(pos.x, pos.y + size.y, size.x)
+    }
+}
+
+/// The nav grid of the loaded chunks, shared with the path tasks
+#[derive(Resource, Default)]
+pub struct Navigation(Arc<NavGrid>);
+
+/// Updates the nav grid of the loaded chunks, and the ones with changed atoms
+pub fn update_navigation(
+    chunk_manager: Res<ChunkManager>,
+    dirty_rects: Res<DirtyRects>,
+    mut navigation: ResMut<Navigation>,
+) {
+    puffin::profile_function!();
+
+    // Every changed atom is in the render rects
+    let outdated = |index: &IVec2, grid: &NavGrid| {
+        !grid.chunks.contains_key(index) || dirty_rects.render.contains_key(index)
+    };
+    let changed = chunk_manager
+        .chunks
+        .keys()
+        .any(|index| outdated(index, &navigation.0))
+        || navigation
+            .0
+            .chunks
+            .keys()
+            .any(|index| !chunk_manager.chunks.contains_key(index));
+    if !changed {
+        return;
+    }
+
+    // Path tasks keep the grid they started with
+    let grid = Arc::make_mut(&mut navigation.0);
+    grid.chunks
+        .retain(|index, _| chunk_manager.chunks.contains_key(index));
+    for (index, chunk) in &chunk_manager.chunks {
+        if outdated(index, grid) {
+            grid.chunks.insert(*index, chunk.blocking_mask());
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NavAbility {
+    /// Walks on the ground, going up stairs and falling down
+    Walk,
+    /// Walks, and flies up and through the air at a higher cost
+    Jetpack,
+    /// Goes anywhere it fits
+    Fly,
+}
+
+/// Moves from a actor position, with their cost
+fn neighbours(grid: &NavGrid, pos: IVec2, size: IVec2, ability: NavAbility) -> Vec<(IVec2, u32)> {
+    let mut moves = vec![];
+    let cost = |dir: IVec2| if dir.x != 0 && dir.y != 0 { 14 } else { 10 };
+
+    if ability == NavAbility::Fly {
+        for (x, y) in (-1..=1).cartesian_product(-1..=1) {
+            let dir = ivec2(x, y);
+            if dir != IVec2::ZERO && grid.fits(pos + dir, size) {
+                moves.push((pos + dir, cost(dir)));
+            }
+        }
+        return moves;
+    }
+
+    let grounded = grid.on_ground(pos, size);
+    if !grounded {
+        for x in -1..=1 {
+            let dir = ivec2(x, 1);
+            if grid.fits(pos + dir, size) {
+                moves.push((pos + dir, cost(dir)));
+            }
+        }
+    }
+
+    if grounded || ability == NavAbility::Jetpack {
+        for x in [-1, 1] {
+            let side = pos + ivec2(x, 0);
+            if grid.fits(side, size) {
+                moves.push((side, 10));
+                continue;
+            }
+            if !grounded {
+                continue;
+            }
+
+            // Up stair-like structures, like `update_actors`
+            for height in 1..=UP_WALK_HEIGHT as i32 {
+                if !grid.fits(pos - ivec2(0, height), size) {
+                    break;
+                }
+                let step = pos + ivec2(x, -height);
+                if grid.fits(step, size) {
+                    moves.push((step, 10 * (height as u32 + 1)));
+                    break;
+                }
+            }
+        }
+    }
+
+    if ability == NavAbility::Jetpack {
+        for x in -1..=1 {
+            let dir = ivec2(x, -1);
+            if grid.fits(pos + dir, size) {
+                moves.push((pos + dir, cost(dir) * 2));
+            }
+        }
+    }
+
+    moves
+}
+
+/// Finds a path with A*, from and to actor top left positions.
+/// Goes to the closest position it found when the goal can't be reached.
+/// Returns the positions to go through, the next one last.
+pub fn find_path(
+    grid: &NavGrid,
+    start: IVec2,
+    goal: IVec2,
+    size: IVec2,
+    ability: NavAbility,
+) -> Vec<IVec2> {
+    let heuristic = |pos: IVec2| {
+        let diff = (goal - pos).abs();
+        (10 * diff.max_element() + 4 * diff.min_element()) as u32
+    };
+
+    let mut open = BinaryHeap::new();
+    let mut visited: HashMap<IVec2, (u32, IVec2)> = HashMap::new();
+    open.push(Reverse((heuristic(start), start.x, start.y)));
+    visited.insert(start, (0, start));
+
+    let mut closest = (heuristic(start), start);
+    let mut explored = 0;
+    while let Some(Reverse((_, x, y))) = open.pop() {
+        let pos = ivec2(x, y);
+        let distance = visited[&pos].0;
+
+        if (goal - pos).abs().max_element() <= 1 {
+            closest = (0, pos);
+            break;
+        }
+        let left = heuristic(pos);
+        if left < closest.0 {
+            closest = (left, pos);
+        }
+
+        explored += 1;
+        if explored > PATH_MAX_NODES {
+            break;
+        }
+
+        for (next, cost) in neighbours(grid, pos, size, ability) {
+            let distance = distance + cost;
+            if visited
+                .get(&next)
+                .is_some_and(|(visited, _)| *visited <= distance)
+            {
+                continue;
+            }
+
+            visited.insert(next, (distance, pos));
+            open.push(Reverse((distance + heuristic(next), next.x, next.y)));
+        }
+    }
+
+    let mut path = vec![];
+    let mut pos = closest.1;
+    while pos != start {
+        path.push(pos);
+        pos = visited[&pos].1;
+    }
+
+    path
+}
+
+/// Finds paths for the actor to it's goal in the background
+#[derive(Component)]
+pub struct Pathfinder {
+    pub ability: NavAbility,
+    /// Top left position the actor is going to
+    pub goal: Option<IVec2>,
+    /// Positions left to go through, the next one last
+    pub path: Vec<IVec2>,
+    /// Goal of the last path
+    planned: Option<IVec2>,
+    task: Option<Task<Vec<IVec2>>>,
+    /// Seconds until the path is found again, as the terrain changes
+    replan: f32,
+}
+
+impl Pathfinder {
+    pub fn new(ability: NavAbility) -> Self {
+        Self {
+            ability,
+            goal: None,
+            path: vec![],
+            planned: None,
+            task: None,
+            replan: 0.,
+        }
+    }
+
+    /// Next position to go to from the actor position, skipping the ones reached
+    pub fn waypoint(&mut self, pos: IVec2) -> Option<IVec2> {
+        while self
+            .path
+            .last()
+            .is_some_and(|next| next.as_vec2().distance(pos.as_vec2()) <= PATH_REACHED)
+        {
+            self.path.pop();
+        }
+
+        self.path.last().copied()
+    }
+}
+
+/// Starts finding the paths of actors with a new goal, or a old path
+pub fn start_paths(
+    navigation: Res<Navigation>,
+    mut pathfinders: Query<(&Actor, &mut Pathfinder)>,
+    time: Res<Time>,
+) {
+    for (actor, mut pathfinder) in &mut pathfinders {
+        pathfinder.replan -= time.delta_secs();
+
+        let Some(goal) = pathfinder.goal else {
+            pathfinder.path.clear();
+            continue;
+        };
+        let moved = pathfinder
+            .planned
+            .is_none_or(|planned| planned.as_vec2().distance(goal.as_vec2()) > PATH_GOAL_MOVED);
+        if pathfinder.task.is_some() || (!moved && pathfinder.replan > 0.) {
+            continue;
+        }
+
+        let grid = navigation.0.clone();
+        let (start, size) = (actor.pos, ivec2(actor.width as i32, actor.height as i32));
+        let ability = pathfinder.ability;
+        pathfinder.task = Some(
+            AsyncComputeTaskPool::get()
+                .spawn(async move { find_path(&grid, start, goal, size, ability) }),
+        );
+        pathfinder.planned = Some(goal);
+        pathfinder.replan = PATH_REPLAN_TIME;
+    }
+}
+
+pub fn poll_paths(mut pathfinders: Query<&mut Pathfinder>) {
+    for mut pathfinder in &mut pathfinders {
+        if pathfinder
+            .task
+            .as_ref()
+            .is_some_and(|task| task.is_finished())
+        {
+            let task = pathfinder.task.take().unwrap();
+            pathfinder.path = block_on(task);
+        }
+    }
+}
+
+pub struct PathfindingPlugin;
+impl Plugin for PathfindingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Navigation>().add_systems(
+            Update,
+            (
+                update_navigation,
+                start_paths.after(update_navigation),
+                poll_paths.after(start_paths),
+            )
+                .run_if(in_state(GameState::Game)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grid of the chunks, with the atoms blocked where `blocked` is true
+    fn grid(chunks: &[IVec2], blocked: impl Fn(IVec2) -> bool) -> NavGrid {
+        let mut grid = NavGrid::default();
+        for index in chunks {
+            let rows = (0..CHUNK_LENGHT as i32)
+                .map(|y| {
+                    (0..CHUNK_LENGHT as i32).fold(0, |mask, x| {
+                        let pos = *index * CHUNK_LENGHT as i32 + ivec2(x, y);
+                        mask | ((blocked(pos) as u64) << x)
+                    })
+                })
+                .collect();
+            grid.chunks.insert(*index, rows);
+        }
+        grid
+    }
+
+    /// Last position of the path, where the actor ends up
+    fn end(path: &[IVec2], start: IVec2) -> IVec2 {
+        path.first().copied().unwrap_or(start)
+    }
+
+    #[test]
+    fn walks_up_stairs() {
+        let (start, size) = (ivec2(20, 38), ivec2(2, 2));
+        let step = |height: i32| {
+            grid(&[IVec2::ZERO], move |pos| {
+                pos.y >= 40 || (pos.x >= 30 && pos.y >= 40 - height)
+            })
+        };
+
+        // A step as high as the actors can walk up
+        let height = UP_WALK_HEIGHT as i32;
+        let goal = ivec2(34, 40 - height - size.y);
+        let path = find_path(&step(height), start, goal, size, NavAbility::Walk);
+        assert!((goal - end(&path, start)).abs().max_element() <= 1);
+
+        // One higher needs a jetpack
+        let goal = goal - ivec2(0, 1);
+        let higher = step(height + 1);
+        let path = find_path(&higher, start, goal, size, NavAbility::Walk);
+        assert!((goal - end(&path, start)).abs().max_element() > 1);
+        let path = find_path(&higher, start, goal, size, NavAbility::Jetpack);
+        assert!((goal - end(&path, start)).abs().max_element() <= 1);
+    }
+
+    #[test]
+    fn spans_chunk_border() {
+        let length = CHUNK_LENGHT as i32;
+        let grid = grid(&[IVec2::ZERO, ivec2(1, 0)], |pos| pos == ivec2(length, 5));
+
+        assert!(grid.row_free(length - 4, 5, 4));
+        assert!(!grid.row_free(length - 4, 5, 5));
+        assert!(grid.row_free(length - 4, 4, 8));
+        assert!(grid.fits(ivec2(length - 3, 0), ivec2(6, 5)));
+        assert!(!grid.fits(ivec2(length - 3, 0), ivec2(6, 6)));
+        // Unloaded chunks are blocked
+        assert!(!grid.row_free(-2, 5, 4));
+    }
+
+    #[test]
+    fn unreachable_goes_closest() {
+        // A wall across the only loaded chunk
+        let grid = grid(&[IVec2::ZERO], |pos| pos.x == 20);
+        let (start, goal, size) = (ivec2(5, 30), ivec2(40, 30), ivec2(2, 2));
+
+        let path = find_path(&grid, start, goal, size, NavAbility::Fly);
+        assert_eq!(end(&path, start), ivec2(18, 30));
+    }
+}
@@ -0,0 +1,379 @@
+use crate::prelude::*;
+
+/// A chunk while it's being generated, passed through every stage in order
+pub struct GenChunk<'a> {
+    pub index: IVec2,
+    pub materials: &'a Materials,
+    /// Terrain noise of each atom, empty until the terrain stage runs
+    pub samples: Vec<GenSample>,
+    pub atoms: [Atom; CHUNK_LEN],
+    /// Background material ids, 0 is no wall
+    pub background: [u8; CHUNK_LEN],
+    /// Entities to spawn with the chunk
+    pub spawns: Vec<StructureSpawn>,
+}
+
+impl<'a> GenChunk<'a> {
+    pub fn new(index: IVec2, materials: &'a Materials) -> Self {
+        Self {
+            index,
+            materials,
+            samples: vec![],
+            atoms: [Atom::default(); CHUNK_LEN],
+            background: [0; CHUNK_LEN],
+            spawns: vec![],
+        }
+    }
+
+    /// Global position of the atom at a chunk index
+    pub fn global(&self, i: usize) -> IVec2 {
+        self.index * CHUNK_LENGHT as i32
+            + ivec2((i % CHUNK_LENGHT) as i32, (i / CHUNK_LENGHT) as i32)
+    }
+
+    /// Sets a new atom with the id, a id of 0 makes it void
+    pub fn set(&mut self, i: usize, id: u8) {
+        self.atoms[i] = if id == 0 {
+            Atom::default()
+        } else {
+            Atom::new(id, self.materials)
+        };
+    }
+
+    /// Gets the terrain sample of a atom, sampling the generator if the terrain stage didn't run
+    pub fn sample(&self, i: usize, generator: &Generator) -> GenSample {
+        match self.samples.get(i) {
+            Some(sample) => sample.clone(),
+            None => generator.sample(self.global(i)),
+        }
+    }
+
+    pub fn is_liquid(&self, id: u8) -> bool {
+        matches!(self.materials[id].default_state, AtomState::Liquid)
+    }
+}
+
+/// A step of the world generation, run on every new chunk in the pipeline order
+pub trait GenStage: Send + Sync {
+    /// Used to find the stage when adding others around it
+    fn name(&self) -> &str;
+    fn run(&self, chunk: &mut GenChunk, generator: &Generator);
+}
+
+/// Samples the terrain shape for every atom, without the caves
+pub struct TerrainStage;
+impl GenStage for TerrainStage {
+    fn name(&self) -> &str {
+        "terrain"
+    }
+
+    fn run(&self, chunk: &mut GenChunk, generator: &Generator) {
+        chunk.samples = (0..CHUNK_LEN)
+            .map(|i| generator.terrain(chunk.global(i)))
+            .collect();
+    }
+}
+
+/// Blends the caves noise into the terrain samples, does nothing without the terrain stage
+pub struct CavesStage;
+impl GenStage for CavesStage {
+    fn name(&self) -> &str {
+        "caves"
+    }
+
+    fn run(&self, chunk: &mut GenChunk, generator: &Generator) {
+        for i in 0..chunk.samples.len() {
+            let pos = chunk.global(i);
+            generator.carve(pos, &mut chunk.samples[i]);
+        }
+    }
+}
+
+/// Picks the atoms and background materials from the biome bands, leaving the liquids as air
+pub struct BandsStage;
+impl GenStage for BandsStage {
+    fn name(&self) -> &str {
+        "bands"
+    }
+
+    fn run(&self, chunk: &mut GenChunk, generator: &Generator) {
+        for i in 0..CHUNK_LEN {
+            let sample = chunk.sample(i, generator);
+            let id = generator
+                .material_for(&sample)
+                .filter(|id| !chunk.is_liquid(*id));
+            chunk.set(i, id.unwrap_or(0));
+            chunk.background[i] = generator.wall_for(&sample, chunk.materials).unwrap_or(0);
+        }
+    }
+}
+
+/// Places the configured deposits that aren't liquids, like ore veins
+pub struct DepositsStage;
+impl GenStage for DepositsStage {
+    fn name(&self) -> &str {
+        "deposits"
+    }
+
+    fn run(&self, chunk: &mut GenChunk, generator: &Generator) {
+        place_deposits(chunk, generator, false);
+    }
+}
+
+/// Fills the air left by the bands stage with their liquids, then places the liquid deposits
+pub struct LiquidsStage;
+impl GenStage for LiquidsStage {
+    fn name(&self) -> &str {
+        "liquids"
+    }
+
+    fn run(&self, chunk: &mut GenChunk, generator: &Generator) {
+        for i in 0..CHUNK_LEN {
+            if !chunk.atoms[i].is_void() {
+                continue;
+            }
+
+            let sample = chunk.sample(i, generator);
+            if let Some(id) = generator
+                .material_for(&sample)
+                .filter(|id| chunk.is_liquid(*id))
+            {
+                chunk.set(i, id);
+            }
+        }
+
+        place_deposits(chunk, generator, true);
+    }
+}
+
+/// Grows the configured decorations on the terrain surfaces
+pub struct DecorationStage;
+impl GenStage for DecorationStage {
+    fn name(&self) -> &str {
+        "decoration"
+    }
+
+    fn run(&self, chunk: &mut GenChunk, generator: &Generator) {
+        place_decorations(chunk, generator);
+    }
+}
+
+/// The ordered generation stages, copied to the generator when the world is loaded.
+/// Plugins can add their own stages on build, like the `StructuresPlugin` does:
+///
+/// ```ignore
+/// app.init_resource::<GenPipeline>();
+/// app.world_mut()
+///     .resource_mut::<GenPipeline>()
+///     .insert_after("liquids", MyStage);
+/// ```
+#[derive(Resource, Clone)]
+pub struct GenPipeline {
+    pub stages: Vec<Arc<dyn GenStage>>,
+}
+
+impl Default for GenPipeline {
+    fn default() -> Self {
+        Self {
+            stages: vec![
+                Arc::new(TerrainStage),
+                Arc::new(CavesStage),
+                Arc::new(BandsStage),
+                Arc::new(DepositsStage),
+                Arc::new(LiquidsStage),
+                Arc::new(DecorationStage),
+            ],
+        }
+    }
+}
+
+impl GenPipeline {
+    /// Adds the stage right after the named one, or at the end if there is none with that name
+    pub fn insert_after(&mut self, name: &str, stage: impl GenStage + 'static) -> &mut Self {
+        let i = self
+            .stages
+            .iter()
+            .position(|stage| stage.name() == name)
+            .map_or(self.stages.len(), |i| i + 1);
+        self.stages.insert(i, Arc::new(stage));
+        self
+    }
+
+    /// Keeps only the named stages, in their pipeline order
+    pub fn only(&mut self, names: &[&str]) -> &mut Self {
+        self.stages.retain(|stage| names.contains(&stage.name()));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flat terrain with no noise: the ground layer goes down to y 121, then it's all cave
+    const CONFIG: &str = r#"#![enable(implicit_some)]
+(
+    seed: 1,
+    scale: 64.0,
+    air_limit: -1000.0,
+    biomes: [
+        (
+            name: "Test",
+            wall: 2,
+            cave_start: 1.0,
+            falloff: 0.1,
+            ground_bands: [(min: 0.75, id: 2)],
+            cave_bands: [(max: 0.6, id: 3)],
+        ),
+    ],
+    deposits: [
+        (id: 4, shape: Blob, cell_size: 32, frequency: 4.0, size: (6.0, 10.0), replace: [2]),
+        (id: 3, shape: Pool, cell_size: 32, frequency: 4.0, size: (6.0, 10.0)),
+    ],
+    decorations: [
+        (id: 4, on: [2], side: Floor, length: (2, 2), chance: 1.0),
+    ],
+)"#;
+
+    /// Void, object, solid, water and gravel
+    fn materials() -> Materials {
+        let state = |default_state| Material {
+            default_state,
+            ..Default::default()
+        };
+        Materials(vec![
+            state(AtomState::Void),
+            state(AtomState::Object),
+            state(AtomState::Solid),
+            state(AtomState::Liquid),
+            state(AtomState::Powder),
+        ])
+    }
+
+    fn generator() -> Generator {
+        Generator::new(ron::de::from_str(CONFIG).unwrap())
+    }
+
+    fn run(stages: &[&dyn GenStage], chunk: &mut GenChunk, generator: &Generator) {
+        for stage in stages {
+            stage.run(chunk, generator);
+        }
+    }
+
+    fn atom(chunk: &GenChunk, x: usize, y: usize) -> u8 {
+        chunk.atoms[y * CHUNK_LENGHT + x].id
+    }
+
+    #[test]
+    fn terrain_samples_every_atom_without_caves() {
+        let (generator, materials) = (generator(), materials());
+
+        let mut chunk = GenChunk::new(ivec2(0, 0), &materials);
+        TerrainStage.run(&mut chunk, &generator);
+        assert_eq!(chunk.samples.len(), CHUNK_LEN);
+        assert!(chunk
+            .samples
+            .iter()
+            .all(|sample| sample.ground && sample.cave == 0.));
+
+        let mut chunk = GenChunk::new(ivec2(0, 3), &materials);
+        TerrainStage.run(&mut chunk, &generator);
+        assert!(chunk
+            .samples
+            .iter()
+            .all(|sample| !sample.ground && sample.cave == 1. && sample.value > 1.));
+    }
+
+    #[test]
+    fn caves_blend_the_cave_noise() {
+        let (generator, materials) = (generator(), materials());
+
+        let mut chunk = GenChunk::new(ivec2(0, 3), &materials);
+        CavesStage.run(&mut chunk, &generator);
+        assert!(chunk.samples.is_empty());
+
+        run(&[&TerrainStage, &CavesStage], &mut chunk, &generator);
+        // The cave noise is 0 everywhere, so 0.5 once normalized
+        assert!(chunk.samples.iter().all(|sample| sample.value == 0.5));
+    }
+
+    #[test]
+    fn bands_leave_liquids_as_air() {
+        let (generator, materials) = (generator(), materials());
+
+        let mut chunk = GenChunk::new(ivec2(0, 0), &materials);
+        run(&[&TerrainStage, &BandsStage], &mut chunk, &generator);
+        assert_eq!(atom(&chunk, 10, 31), 0);
+        assert_eq!(atom(&chunk, 10, 32), 2);
+        assert_eq!(chunk.background[32 * CHUNK_LENGHT + 10], 2);
+
+        let mut chunk = GenChunk::new(ivec2(0, 3), &materials);
+        run(
+            &[&TerrainStage, &CavesStage, &BandsStage],
+            &mut chunk,
+            &generator,
+        );
+        assert!(chunk.atoms.iter().all(|atom| atom.is_void()));
+        assert!(chunk.background.iter().all(|wall| *wall == 2));
+    }
+
+    #[test]
+    fn deposits_skip_liquids() {
+        let (generator, materials) = (generator(), materials());
+
+        let mut chunk = GenChunk::new(ivec2(0, 0), &materials);
+        run(&[&TerrainStage, &BandsStage], &mut chunk, &generator);
+        let before = chunk.atoms;
+        DepositsStage.run(&mut chunk, &generator);
+
+        let changed: Vec<_> = (0..CHUNK_LEN)
+            .filter(|i| chunk.atoms[*i] != before[*i])
+            .collect();
+        assert!(!changed.is_empty());
+        assert!(changed
+            .iter()
+            .all(|i| before[*i].id == 2 && chunk.atoms[*i].id == 4));
+    }
+
+    #[test]
+    fn liquids_fill_bands_and_pools() {
+        let (generator, materials) = (generator(), materials());
+
+        let mut chunk = GenChunk::new(ivec2(0, 3), &materials);
+        run(
+            &[&TerrainStage, &CavesStage, &BandsStage, &LiquidsStage],
+            &mut chunk,
+            &generator,
+        );
+        assert!(chunk.atoms.iter().any(|atom| atom.id == 3));
+        assert!(chunk
+            .atoms
+            .iter()
+            .all(|atom| atom.id == 3 || atom.is_void()));
+
+        // Pools are only placed by this stage
+        let mut chunk = GenChunk::new(ivec2(0, 0), &materials);
+        run(&[&TerrainStage, &BandsStage], &mut chunk, &generator);
+        assert!(chunk.atoms.iter().all(|atom| atom.id != 3));
+        LiquidsStage.run(&mut chunk, &generator);
+        assert!(chunk.atoms.iter().any(|atom| atom.id == 3));
+    }
+
+    #[test]
+    fn decorations_grow_from_the_surface() {
+        let (generator, materials) = (generator(), materials());
+
+        let mut chunk = GenChunk::new(ivec2(0, 0), &materials);
+        run(
+            &[&TerrainStage, &BandsStage, &DecorationStage],
+            &mut chunk,
+            &generator,
+        );
+        for x in 0..CHUNK_LENGHT {
+            assert_eq!(atom(&chunk, x, 29), 0);
+            assert_eq!(atom(&chunk, x, 30), 4);
+            assert_eq!(atom(&chunk, x, 31), 4);
+            assert_eq!(atom(&chunk, x, 32), 2);
+        }
+    }
+}
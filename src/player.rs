@@ -8,18 +8,21 @@ use crate::prelude::*;
 pub struct Player {
     fuel: f32,
     state: PlayerState,
+    //Actor position, saved when the player is dropped
+    pos: IVec2,
 }
 
-impl Default for Player {
-    fn default() -> Self {
+impl Player {
+    pub fn new(pos: IVec2) -> Self {
         Self {
             fuel: FUEL_MAX,
             state: PlayerState::default(),
+            pos,
         }
     }
 }
 
-impl Drop for Actor {
+impl Drop for Player {
     fn drop(&mut self) {
         let file = File::create("assets/world/player").unwrap();
         let mut buffered = BufWriter::new(file);
@@ -35,6 +38,7 @@ pub enum PlayerState {
     Dead,
     Jumping(f32),
     Jetpack(bool),
+    Swimming,
 }
 
 #[derive(Component, Default)]
@@ -44,7 +48,10 @@ pub struct Tool;
 pub struct ToolFront;
 
 #[derive(Component)]
-pub struct Life(f32);
+pub struct JetpackExhaust;
+
+#[derive(Component)]
+pub struct Life(pub f32);
 
 pub fn player_setup(
     mut commands: Commands,
@@ -68,6 +75,7 @@ pub fn player_setup(
         pos,
         vel: vec2(0., 0.),
         colliding: None,
+        submerged: 0.,
     };
 
     let player_handle = asset_server.load("player/player_sheet.png");
@@ -90,6 +98,13 @@ pub fn player_setup(
     let tool_front_ent = commands
         .spawn((Transform::from_translation(vec3(5., 0., 0.)), ToolFront))
         .id();
+    let exhaust_ent = commands
+        .spawn((
+            Transform::from_translation(vec3(-3., -5., 0.)),
+            Emitter::new("jetpack"),
+            JetpackExhaust,
+        ))
+        .id();
     let tool_ent = commands
         .spawn(tool_bundle)
         .insert(Tool)
@@ -100,7 +115,7 @@ pub fn player_setup(
     commands
         .spawn((
             player_actor.clone(),
-            Player::default(),
+            Player::new(pos),
             Sprite::from_atlas_image(player_handle, atlas),
             player_transform,
             animation_indices,
@@ -113,7 +128,7 @@ pub fn player_setup(
             ),
             Life(100.),
         ))
-        .add_child(tool_ent);
+        .add_children(&[tool_ent, exhaust_ent]);
 
     //Life
     commands.spawn((
@@ -193,15 +208,16 @@ pub fn update_player(
 
     let (inputs, mut scroll_evr) = input;
 
+    let on_ground = on_ground(&chunk_manager, &actor);
+    let swimming = actor.submerged >= SWIM_SUBMERGED;
+
     // Movement
     let x = inputs.right - inputs.left;
-    actor.vel.x = x * RUN_SPEED;
-
-    let on_ground = on_ground(&chunk_manager, &actor);
+    actor.vel.x = x * if swimming { SWIM_SPEED } else { RUN_SPEED };
 
     // Refuel
     if on_ground {
-        player.fuel = (player.fuel + FUEL_REGEN).clamp(0., Player::default().fuel);
+        player.fuel = (player.fuel + FUEL_REGEN).clamp(0., FUEL_MAX);
     }
 
     if on_ground {
@@ -210,10 +226,15 @@ pub fn update_player(
         } else {
             player.state = PlayerState::Idle
         }
+    } else if swimming {
+        player.state = PlayerState::Swimming;
+    } else if matches!(player.state, PlayerState::Swimming) {
+        //Leaps out of the liquid while holding space
+        player.state = PlayerState::Jumping(time.elapsed_secs_wrapped());
     }
 
     // Jump
-    if inputs.jump_just_pressed {
+    if inputs.jump_just_pressed && !swimming {
         if on_ground {
             actor.vel.y -= JUMP_MAG;
             player.state = PlayerState::Jumping(time.elapsed_secs_wrapped());
@@ -246,6 +267,12 @@ pub fn update_player(
         *up = new_up
     };
 
+    // Swim
+    if swimming {
+        let dir = inputs.down - inputs.jump_pressed as u8 as f32;
+        actor.vel.y = (actor.vel.y + dir * SWIM_FORCE).clamp(-SWIM_MAX, SWIM_MAX);
+    }
+
     //Scroll
     for ev in scroll_evr.read() {
         if ev.unit == MouseScrollUnit::Line && inputs.ctrl {
@@ -279,9 +306,11 @@ pub fn update_player_nc(
         Entity,
     )>,
     mut ev_damage: EventWriter<DamageEvent>,
+    mut exhaust: Query<&mut Emitter, With<JetpackExhaust>>,
 ) {
     let (mut player_life_node, mut player_fuel_node) = (nodes.0.single_mut(), nodes.1.single_mut());
     let (mut actor, mut player, mut anim_idxs, life, ent) = player.single_mut();
+    player.pos = actor.pos;
 
     // Gravity
     if actor.vel.y < PLAYER_TERM_VEL as f32 {
@@ -297,7 +326,7 @@ pub fn update_player_nc(
     (anim_idxs.first, anim_idxs.last) = match player.state {
         PlayerState::Idle => (0, 1),
         PlayerState::Walking => (8, 11),
-        PlayerState::Jumping { .. } => (16, 23),
+        PlayerState::Jumping { .. } | PlayerState::Swimming => (16, 23),
         PlayerState::Jetpack(up) => {
             if up {
                 (24, 26)
@@ -308,6 +337,9 @@ pub fn update_player_nc(
         PlayerState::Dead => (0, 0), //TODO
     };
 
+    //Jetpack exhaust
+    exhaust.single_mut().on = matches!(player.state, PlayerState::Jetpack(true));
+
     //Fall Damage
     if let Some(speed) = actor.colliding {
         if speed >= 10. {
@@ -475,7 +507,6 @@ pub fn dead(
 }
 
 pub fn tool_system(
-    mut commands: Commands,
     mut tool: Query<
         (
             &mut Transform,
@@ -496,8 +527,10 @@ pub fn tool_system(
         Res<Assets<Materials>>,
         Res<MaterialsHandle>,
     ),
-    mut ev_item: EventWriter<ItemEvent>,
+    mut events: (EventWriter<ItemEvent>, EventWriter<CarveEvent>),
+    mut particles: ResMut<Particles>,
 ) {
+    let (ev_item, ev_carve) = (&mut events.0, &mut events.1);
     let (mut tool_transform, tool_gtransform, mut tool_sprite, mut visibility) = tool.single_mut();
     let (camera, camera_gtransform) = camera.single_mut();
     let (window, mut player) = querys;
@@ -523,9 +556,10 @@ pub fn tool_system(
     };
 
     //Don't use tool if atom is solid or we don't have nothing on selected slot
+    //Solids can only be placed as background walls
     if let Some(slot) = inventory.slots[inventory.selected] {
         if let Item::Atom(atom) = slot.item {
-            if atom.is_solid() {
+            if atom.is_solid() && !inputs.background {
                 *visibility = Visibility::Hidden;
                 return;
             }
@@ -552,9 +586,10 @@ pub fn tool_system(
         tool_transform.translation.x.abs() * (flip_bool as i8 * 2 - 1) as f32;
 
     //Don't use tool if atom is solid or we don't have nothing on selected slot
+    //Solids can only be placed as background walls
     if let Some(slot) = inventory.slots[inventory.selected] {
         if let Item::Atom(atom) = slot.item {
-            if atom.is_solid() {
+            if atom.is_solid() && !inputs.background {
                 *visibility = Visibility::Hidden;
                 return;
             }
@@ -577,7 +612,83 @@ pub fn tool_system(
     let tool_front = center_vec_y_flipped + tool_slope * 5.;
 
     let mut pos_to_update = vec![];
-    if inputs.push {
+    let mut background_to_update = vec![];
+    if inputs.push && inputs.background {
+        //Place walls where there is nothing in front or behind
+        let center = tool_front + tool_slope * 8.;
+        let selected = match inventory.slots[inventory.selected].map(|slot| slot.item) {
+            Some(Item::Atom(atom))
+                if matches!(atom.state, AtomState::Solid | AtomState::Powder) =>
+            {
+                Some(atom)
+            }
+            _ => None,
+        };
+
+        for _ in 0..6 {
+            let Some(selected) = selected else {
+                break;
+            };
+            let rand_angle = fastrand::f32() * std::f32::consts::TAU;
+            let vec = center + vec2(rand_angle.cos(), rand_angle.sin()) * fastrand::f32() * 4.;
+            let chunk_pos = global_to_chunk(vec.as_ivec2());
+
+            if !chunk_manager
+                .get_atom(&chunk_pos)
+                .is_some_and(|atom| atom.is_void())
+            {
+                continue;
+            }
+            if let Some(wall) = chunk_manager.get_mut_background(chunk_pos) {
+                if wall.is_void() {
+                    *wall = Atom::new(selected.id, materials);
+                    background_to_update.push(chunk_pos);
+                    ev_item.send(ItemEvent::RemoveSelected);
+                }
+            }
+        }
+    } else if inputs.pull && inputs.background {
+        //Pull the first wall found behind empty space on each line
+        let center_bound = tool_front + tool_slope * TOOL_DISTANCE;
+
+        let bound1 = (center_bound + bound_slope * TOOL_RANGE).as_ivec2();
+        let bound2 = (center_bound + -bound_slope * TOOL_RANGE).as_ivec2();
+
+        for bound_vec in Line::new(bound1, bound2 - bound1) {
+            for vec in Line::new(
+                (tool_front - 4. * tool_slope).as_ivec2(),
+                bound_vec - (tool_front - 4. * tool_slope).as_ivec2(),
+            ) {
+                let chunk_pos = global_to_chunk(vec);
+                if (vec.distance_squared((tool_front - 6. * tool_slope).as_ivec2()) as f32).sqrt()
+                    < 6.
+                {
+                    continue;
+                }
+
+                //Walls can't be reached through atoms
+                match chunk_manager.get_atom(&chunk_pos) {
+                    Some(atom) if atom.is_void() || atom.is_object() => {}
+                    _ => break,
+                }
+
+                if let Some(wall) = chunk_manager.get_mut_background(chunk_pos) {
+                    if !wall.is_void() && inventory.can_add(Item::Atom(*wall)) {
+                        particles.spawn(Particle {
+                            atom: *wall,
+                            pos: chunk_pos.to_global().as_vec2(),
+                            state: PartState::Follow(tool_front_ent.single()),
+                            ..Default::default()
+                        });
+
+                        background_to_update.push(chunk_pos);
+                        *wall = Atom::default();
+                        break;
+                    }
+                }
+            }
+        }
+    } else if inputs.push {
         let new_tool_front = tool_front + tool_slope * 3.5;
         let n = 6;
 
@@ -601,7 +712,7 @@ pub fn tool_system(
                             let angle = fastrand::f32() * 0.5 - 0.25;
                             let vel = (tool_slope * 10. * (fastrand::f32() * 0.2 + 0.8))
                                 .rotate(vec2(angle.cos(), angle.sin()));
-                            commands.spawn(Particle {
+                            particles.spawn(Particle {
                                 atom: Atom::new(atom.id, materials),
                                 velocity: vel,
                                 pos: vec,
@@ -633,9 +744,19 @@ pub fn tool_system(
                 }
 
                 if let Some(atom) = chunk_manager.get_mut_atom(chunk_pos) {
-                    if !atom.is_void() && !atom.is_object() && inventory.can_add(Item::Atom(*atom))
-                    {
-                        commands.spawn(Particle {
+                    //Rigidbodies are carved, their atoms following the tool
+                    if atom.is_object() {
+                        ev_carve.send(CarveEvent {
+                            center: vec.as_vec2(),
+                            radius: 1.,
+                            force: 0.,
+                            follow: Some(tool_front_ent.single()),
+                        });
+                        break;
+                    }
+
+                    if !atom.is_void() && inventory.can_add(Item::Atom(*atom)) {
+                        particles.spawn(Particle {
                             atom: *atom,
                             pos: chunk_pos.to_global().as_vec2(),
                             state: PartState::Follow(tool_front_ent.single()),
@@ -651,16 +772,14 @@ pub fn tool_system(
         }
     }
 
-    let mut chunks = HashSet::new();
+    for pos in background_to_update {
+        update_dirty_rects(&mut dirty_rects.background, pos);
+    }
+
+    // The render rects also get the chunk colliders rebuilt
     for pos in pos_to_update {
         update_dirty_rects_3x3(&mut dirty_rects.current, pos);
         update_dirty_rects(&mut dirty_rects.render, pos);
-        chunks.insert(pos.chunk);
-    }
-
-    for chunk in chunks {
-        let chunk = chunk_manager.chunks.get(&chunk).unwrap();
-        commands.entity(chunk.entity.unwrap()).remove::<Collider>();
     }
 }
 
@@ -696,6 +815,9 @@ pub fn get_input(
     if keys.pressed(KeyCode::KeyD) {
         inputs.right = 1.;
     }
+    if keys.pressed(KeyCode::KeyS) {
+        inputs.down = 1.;
+    }
 
     //Tool
     if mouse_buttons.pressed(MouseButton::Left) {
@@ -729,10 +851,44 @@ pub fn get_input(
         inputs.inventory_toggle = true;
     }
 
+    //World map open and close, pan and zoom
+    if keys.just_released(KeyCode::KeyM) {
+        inputs.map_toggle = true;
+    }
+
+    for (key, dir) in [
+        (KeyCode::ArrowLeft, Vec2::NEG_X),
+        (KeyCode::ArrowRight, Vec2::X),
+        (KeyCode::ArrowUp, Vec2::NEG_Y),
+        (KeyCode::ArrowDown, Vec2::Y),
+    ] {
+        if keys.pressed(key) {
+            inputs.map_pan += dir;
+        }
+    }
+
+    if keys.just_pressed(KeyCode::Equal) {
+        inputs.map_zoom += 1.;
+    } else if keys.just_pressed(KeyCode::Minus) {
+        inputs.map_zoom -= 1.;
+    }
+
+    //World captures
+    if keys.just_pressed(KeyCode::F12) {
+        inputs.screenshot = true;
+    }
+    if keys.just_pressed(KeyCode::F11) {
+        inputs.time_lapse_toggle = true;
+    }
+
     //Check if we change selected or zoom
     if keys.pressed(KeyCode::ControlLeft) {
         inputs.ctrl = true;
     }
+
+    if keys.pressed(KeyCode::ShiftLeft) {
+        inputs.background = true;
+    }
 }
 
 pub fn clear_input(mut inputs: ResMut<Inputs>) {
@@ -743,6 +899,8 @@ pub fn clear_input(mut inputs: ResMut<Inputs>) {
 pub struct Inputs {
     left: f32,
     right: f32,
+    //Swims down
+    down: f32,
 
     pull: bool,
     push: bool,
@@ -752,8 +910,17 @@ pub struct Inputs {
 
     numbers: [bool; 8],
     ctrl: bool,
+    //Tool uses the background walls
+    background: bool,
 
     pub inventory_toggle: bool,
+
+    pub map_toggle: bool,
+    pub map_pan: Vec2,
+    pub map_zoom: f32,
+
+    pub screenshot: bool,
+    pub time_lapse_toggle: bool,
 }
 
 pub struct PlayerPlugin;
@@ -768,6 +935,7 @@ impl Plugin for PlayerPlugin {
                 dead,
                 update_player_sprite.after(update_actors),
                 tool_system
+                    .after(update_rigidibodies)
                     .before(chunk_manager_update)
                     .before(update_particles),
             )
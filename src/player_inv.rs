@@ -332,6 +332,61 @@ pub fn update_numbers(
     }
 }
 
+/// A item laying on the world, picked up when the player touches it
+#[derive(Component, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pickup {
+    pub item: Item,
+    pub number: u16,
+    /// Global position
+    pub pos: Vec2,
+}
+
+impl Pickup {
+    pub fn sprite(&self) -> (Sprite, Transform) {
+        let color = match self.item {
+            Item::Atom(atom) => Color::srgba_u8(atom.color[0], atom.color[1], atom.color[2], 255),
+            Item::SmartTool => Color::WHITE,
+        };
+
+        (
+            Sprite {
+                color,
+                custom_size: Some(Vec2::splat(3.)),
+                ..Default::default()
+            },
+            Transform::from_xyz(self.pos.x, -self.pos.y, PARTICLE_LAYER),
+        )
+    }
+}
+
+pub fn collect_pickups(
+    mut commands: Commands,
+    pickups: Query<(&Pickup, Entity)>,
+    player: Query<&Actor, With<Player>>,
+    mut ev_item: EventWriter<ItemEvent>,
+    inventory: Res<Inventory>,
+) {
+    let Ok(actor) = player.get_single() else {
+        return;
+    };
+    let min = actor.pos.as_vec2();
+    let max = min + vec2(actor.width as f32, actor.height as f32);
+
+    for (pickup, ent) in pickups.iter() {
+        let pos = pickup.pos;
+        if pos.x < min.x || pos.y < min.y || pos.x >= max.x || pos.y >= max.y {
+            continue;
+        }
+
+        if inventory.can_add(pickup.item) {
+            for _ in 0..pickup.number {
+                ev_item.send(ItemEvent::AddOne(pickup.item));
+            }
+            commands.entity(ent).despawn();
+        }
+    }
+}
+
 #[derive(Event)]
 pub enum ItemEvent {
     AddOne(Item),
@@ -571,6 +626,7 @@ impl Plugin for PlayerInvPlugin {
                 item_events.after(show_inventory),
                 show_inventory,
                 slot_button.before(update_numbers).before(item_events),
+                collect_pickups.before(item_events),
             )
                 .run_if(in_state(GameState::Game)),
         )
@@ -0,0 +1,203 @@
+use bevy::image::{CompressedImageFormats, ImageSampler, ImageType};
+use bevy::render::{render_asset::RenderAssetUsages, render_resource::*};
+
+use crate::prelude::*;
+
+const PREVIEW_USAGE: &str = "Usage: astratomic preview [--config <path>] [--seed <seed>] \
+[--rect <chunk x> <chunk y> <width> <height>] [--out <path>] [--masks] [--stages <a,b,..>]";
+
+/// Colors of the biomes on the biome mask, repeated if there are more biomes
+const BIOME_COLORS: [[u8; 3]; 8] = [
+    [76, 153, 0],
+    [230, 200, 90],
+    [150, 220, 40],
+    [40, 110, 220],
+    [220, 60, 20],
+    [150, 70, 200],
+    [40, 200, 190],
+    [200, 80, 140],
+];
+
+/// Saves RGBA8 pixels as a PNG file
+pub fn save_png(path: &str, width: u32, height: u32, data: Vec<u8>) {
+    let image = Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::MAIN_WORLD,
+    );
+
+    image.try_into_dynamic().unwrap().save(path).unwrap();
+}
+
+/// Loads a image from the assets folder without the asset server
+fn load_image(path: &str) -> Image {
+    let bytes = std::fs::read(format!("assets/{path}")).unwrap();
+    let extension = path.rsplit('.').next().unwrap_or_default();
+
+    Image::from_buffer(
+        &bytes,
+        ImageType::Extension(extension),
+        CompressedImageFormats::NONE,
+        true,
+        ImageSampler::Default,
+        RenderAssetUsages::MAIN_WORLD,
+    )
+    .unwrap()
+}
+
+/// Generates a rectangle of chunks without opening the game and saves it as a PNG,
+/// optionally with biome and cave masks next to it
+pub fn preview(args: &[String]) {
+    let option = |name: &str, count: usize| -> Option<&[String]> {
+        let i = args.iter().position(|arg| arg == name)?;
+        let values = args.get(i + 1..i + 1 + count);
+        Some(values.unwrap_or_else(|| panic!("Missing value for {name}. {PREVIEW_USAGE}")))
+    };
+    let parse = |value: &String| value.parse::<i32>().expect(PREVIEW_USAGE);
+
+    let config_path = option("--config", 1).map_or("assets/gen.ron", |v| v[0].as_str());
+    let out = option("--out", 1).map_or("preview.png", |v| v[0].as_str());
+    let (pos, size) = option("--rect", 4).map_or((ivec2(-16, -8), ivec2(32, 24)), |v| {
+        (
+            ivec2(parse(&v[0]), parse(&v[1])),
+            ivec2(parse(&v[2]), parse(&v[3])),
+        )
+    });
+    if size.x <= 0 || size.y <= 0 {
+        panic!("The --rect width and height have to be positive. {PREVIEW_USAGE}");
+    }
+    let masks = args.contains(&"--masks".to_string());
+
+    let bytes = std::fs::read(config_path).unwrap();
+    let mut config = ron::de::from_bytes::<GeneratorConfig>(&bytes).unwrap();
+    if let Some(seed) = option("--seed", 1) {
+        config.seed = seed[0].parse().expect(PREVIEW_USAGE);
+    }
+
+    let bytes = std::fs::read("assets/atoms.ron").unwrap();
+    let materials = ron::de::from_bytes::<Materials>(&bytes).unwrap();
+
+    let mut generator = Generator::new(config);
+    generator.structures = load_structure_defs()
+        .into_iter()
+        .map(|def| {
+            let image = load_image(&def.image);
+            Structure::new(def, &image)
+        })
+        .collect();
+
+    // Same stages as the game, optionally only some of them to see what each one does
+    let mut pipeline = GenPipeline::default();
+    pipeline.insert_after("liquids", StructuresStage);
+    if let Some(stages) = option("--stages", 1) {
+        pipeline.only(&stages[0].split(',').collect::<Vec<_>>());
+    }
+    generator.stages = pipeline.stages;
+    let names: Vec<_> = generator.stages.iter().map(|stage| stage.name()).collect();
+
+    println!(
+        "Generating {}x{} chunks from {} with seed {}, stages: {}",
+        size.x,
+        size.y,
+        config_path,
+        generator.config.seed,
+        names.join(", ")
+    );
+
+    let indices: Vec<IVec2> = (0..size.y)
+        .flat_map(|y| (0..size.x).map(move |x| pos + ivec2(x, y)))
+        .collect();
+
+    // Splits the chunks between threads, keeping their order
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunks: Vec<Chunk> = std::thread::scope(|scope| {
+        let handles: Vec<_> = indices
+            .chunks(indices.len().div_ceil(threads).max(1))
+            .map(|indices| {
+                let (generator, materials) = (&generator, &materials);
+                scope.spawn(move || {
+                    indices
+                        .iter()
+                        .map(|index| Chunk::new(Handle::default(), *index, materials, generator))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    let (width, height) = (
+        size.x as u32 * CHUNK_LENGHT as u32,
+        size.y as u32 * CHUNK_LENGHT as u32,
+    );
+    let offset = |index: IVec2, atom: usize| {
+        let local = ivec2((atom % CHUNK_LENGHT) as i32, (atom / CHUNK_LENGHT) as i32);
+        let image_pos = (index - pos) * CHUNK_LENGHT as i32 + local;
+        (image_pos.y as usize * width as usize + image_pos.x as usize) * 4
+    };
+
+    let mut data = vec![0; width as usize * height as usize * 4];
+    for (index, chunk) in indices.iter().zip(&chunks) {
+        for (i, atom) in chunk.atoms.iter().enumerate() {
+            let pixel = offset(*index, i);
+            data[pixel..pixel + 4].copy_from_slice(&atom.color);
+        }
+    }
+    save_png(out, width, height, data);
+    println!("Saved {out}");
+
+    if !masks {
+        return;
+    }
+
+    let stem = out.strip_suffix(".png").unwrap_or(out);
+    let mut biomes = vec![0; width as usize * height as usize * 4];
+    let mut caves = vec![0; width as usize * height as usize * 4];
+    for (index, chunk) in indices.iter().zip(&chunks) {
+        for (i, atom) in chunk.atoms.iter().enumerate() {
+            let pixel = offset(*index, i);
+            let global = *index * CHUNK_LENGHT as i32
+                + ivec2((i % CHUNK_LENGHT) as i32, (i / CHUNK_LENGHT) as i32);
+            let sample = generator.terrain(global);
+
+            // Air is darker, so the terrain shape can still be seen
+            let [r, g, b] = BIOME_COLORS[sample.biome % BIOME_COLORS.len()];
+            let shade = if atom.is_void() { 2 } else { 1 };
+            biomes[pixel..pixel + 4].copy_from_slice(&[r / shade, g / shade, b / shade, 255]);
+
+            let cave = match (sample.ground, atom.is_void()) {
+                (true, true) => [0, 0, 0, 255],
+                (true, false) => [110, 110, 110, 255],
+                (false, true) => [60, 20, 20, 255],
+                (false, false) => [230, 230, 230, 255],
+            };
+            caves[pixel..pixel + 4].copy_from_slice(&cave);
+        }
+    }
+
+    let biomes_path = format!("{stem}_biomes.png");
+    save_png(&biomes_path, width, height, biomes);
+    println!("Saved {biomes_path}");
+    for (i, biome) in generator.config.biomes.iter().enumerate() {
+        println!(
+            "  {:?} {}",
+            BIOME_COLORS[i % BIOME_COLORS.len()],
+            biome.name
+        );
+    }
+
+    let caves_path = format!("{stem}_caves.png");
+    save_png(&caves_path, width, height, caves);
+    println!("Saved {caves_path}");
+    println!("  Gray is the ground layer, white the cave layer and red the cave air");
+}
@@ -1,3 +1,5 @@
+use bevy::render::{render_asset::RenderAssetUsages, render_resource::*};
+
 use crate::prelude::*;
 use geo::{SimplifyVwPreserve, TriangulateEarcut};
 use itertools::Itertools;
@@ -9,53 +11,163 @@ pub struct Rigidbody {
     pub height: u8,
     // Positions to remove Object atom later
     pub filled: Vec<ChunkPos>,
+    /// Atoms were removed, so the image and collider need to be rebuilt
+    pub damaged: bool,
+    /// Pushes liquids out of the way as particles, instead of going through them
+    pub displace: bool,
+    /// Sleeping, so it's atoms stay stamped on the chunks until it wakes or the terrain changes
+    pub resting: bool,
 }
 
-#[derive(Component, Default)]
-pub struct RigidbodyHandle(Handle<Image>, Vec2);
+impl Rigidbody {
+    /// Global top left position and angle of the rigidbody, with y going down like the atoms
+    pub fn placement(transform: &Transform) -> (Vec2, f32) {
+        let angle = -transform.rotation.to_euler(EulerRot::XYZ).2;
+        let mut top_left = transform.translation.xy();
+        top_left.y *= -1.;
+        (top_left, angle)
+    }
+}
 
-#[derive(Component)]
-pub struct Hydrated;
+/// A rigidbody loaded from a RON file in `assets/rigidbodies`, named after the file
+#[derive(Clone, Debug, Deserialize)]
+pub struct RigidbodyDef {
+    /// Image path, relative to the assets folder
+    pub image: String,
+    /// Image of the same size to map the materials from, if the image colors don't match them
+    #[serde(default)]
+    pub mask: Option<String>,
+
+    /// Material of opaque pixels, if the colors don't map them
+    pub id: u8,
+    /// Pixel colors to materials, a id of 0 leaves a hole
+    #[serde(default)]
+    pub colors: ColorMapping,
+
+    #[serde(default = "default_density")]
+    pub density: f32,
+    #[serde(default = "default_friction")]
+    pub friction: f32,
+    /// Pushes liquids out of the way, if false the liquid stays around the rigidbody atoms
+    #[serde(default = "default_displace")]
+    pub displace: bool,
+}
 
-//TODO Add rigidbody file
-pub fn _load_images(mut commands: Commands, server: Res<AssetServer>) {
-    let image: Handle<Image> = server.load("tree.png");
-    commands.spawn(RigidbodyHandle(image.clone(), vec2(64., -64.)));
+pub fn default_density() -> f32 {
+    1.
+}
+
+pub fn default_friction() -> f32 {
+    0.5
+}
+
+fn default_displace() -> bool {
+    true
+}
+
+/// Rigidbody definitions by name and their loading images, waited on before the game starts
+#[derive(Resource, Default)]
+pub struct RigidbodyAssets(
+    pub HashMap<String, (RigidbodyDef, Handle<Image>, Option<Handle<Image>>)>,
+);
+
+impl RigidbodyAssets {
+    pub fn is_loaded(&self, asset_server: &AssetServer) -> bool {
+        self.0.values().all(|(_, image, mask)| {
+            asset_server.is_loaded(image) && mask.iter().all(|mask| asset_server.is_loaded(mask))
+        })
+    }
+}
+
+pub fn rigidbodies_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let mut rigidbodies = HashMap::new();
+
+    if let Ok(dir) = std::fs::read_dir("assets/rigidbodies") {
+        for path in dir.flatten().map(|entry| entry.path()) {
+            if !path.extension().is_some_and(|ext| ext == "ron") {
+                continue;
+            }
+
+            let name = path.file_stem().unwrap().to_string_lossy().to_string();
+            let bytes = std::fs::read(&path).unwrap();
+            let def = ron::de::from_bytes::<RigidbodyDef>(&bytes).unwrap();
+
+            let image = asset_server.load(&def.image);
+            let mask = def.mask.as_ref().map(|mask| asset_server.load(mask));
+            rigidbodies.insert(name, (def, image, mask));
+        }
+    }
 
-    let image: Handle<Image> = server.load("player/player_tool.png");
-    commands.spawn(RigidbodyHandle(image.clone(), vec2(0., 0.)));
-    let image: Handle<Image> = server.load("player/jetpack.png");
-    commands.spawn(RigidbodyHandle(image.clone(), vec2(-64., 0.)));
+    commands.insert_resource(RigidbodyAssets(rigidbodies));
 }
 
+/// Name of the rigidbody definition and global top left position of a rigidbody to be spawned
+#[derive(Component, Default)]
+pub struct RigidbodyHandle(pub String, pub Vec2);
+
+#[derive(Component)]
+pub struct Hydrated;
+
 pub fn add_rigidbodies(
     mut commands: Commands,
-    images: Res<Assets<Image>>,
+    mut images: ResMut<Assets<Image>>,
+    rigidbody_assets: Res<RigidbodyAssets>,
     handles: Query<(Entity, &RigidbodyHandle), Without<Hydrated>>,
+    materials: (Res<Assets<Materials>>, Res<MaterialsHandle>),
 ) {
+    let materials = materials.0.get(&materials.1 .0).unwrap();
+
     for (ent, handle) in &handles {
-        let Some(image) = images.get(&handle.0) else {
+        let Some((def, image_handle, mask)) = rigidbody_assets.0.get(&handle.0) else {
+            error!("Rigidbody {} not found in assets/rigidbodies", handle.0);
+            commands.entity(ent).despawn();
             continue;
         };
+        let Some(image) = images.get(image_handle) else {
+            continue;
+        };
+        let mask = mask.as_ref().and_then(|mask| images.get(mask));
 
         let rigidbody = Rigidbody {
-            atoms: image_atoms(image),
+            atoms: body_atoms(def, image, mask, materials),
             height: image.height() as u8,
             width: image.width() as u8,
             filled: vec![],
+            damaged: false,
+            displace: def.displace,
+            resting: false,
         };
 
-        let collider = get_collider(&image_values(image), image.width(), image.height()).unwrap();
+        let Some(collider) = get_collider(
+            &atom_values(&rigidbody.atoms),
+            rigidbody.width as u32,
+            rigidbody.height as u32,
+        ) else {
+            commands.entity(ent).despawn();
+            continue;
+        };
 
+        // The handle entity becomes the rigidbody, so joints waiting on it can find it
         commands
-            .spawn(collider)
+            .entity(ent)
+            .insert(collider)
             .insert(rigidbody)
             .insert(RapierRigidbody::Dynamic)
+            .insert(ColliderMassProperties::Density(def.density))
+            .insert(Friction::coefficient(def.friction))
             .insert(bevy_rapier2d::prelude::Velocity::zero())
             .insert(bevy_rapier2d::prelude::ExternalImpulse::default())
+            .insert(ExternalForce::default())
+            .insert(Damping::default())
+            .insert(Sleeping::default())
             .insert(ReadMassProperties::default())
             .insert(Sprite {
-                image: handle.0.clone(),
+                // Each rigidbody has it's own image, as it changes when it's damaged
+                image: images.add(rigidbody_image(
+                    &rigidbody.atoms,
+                    rigidbody.width,
+                    rigidbody.height,
+                )),
 
                 anchor: bevy::sprite::Anchor::TopLeft,
 
@@ -65,28 +177,71 @@ pub fn add_rigidbodies(
                 handle.1.x,
                 -handle.1.y,
                 RIGIDBODY_LAYER,
-            ));
+            ))
+            .insert(Hydrated);
+    }
+}
 
-        commands.entity(ent).insert(Hydrated);
+/// First atom that isn't a object, going from the global `pos` in the `step` direction
+fn past_objects(
+    chunk_manager: &ChunkManager,
+    mut pos: IVec2,
+    step: IVec2,
+    max: i32,
+) -> Option<Atom> {
+    for _ in 0..max {
+        pos += step;
+        let atom = chunk_manager.get_atom(&global_to_chunk(pos))?;
+        if !atom.is_object() {
+            return Some(*atom);
+        }
     }
+    None
 }
 
 pub fn update_rigidibodies(
-    mut commands: Commands,
     mut chunk_manager: ResMut<ChunkManager>,
-    mut rigidbodies: Query<(&Transform, &mut Rigidbody, &Velocity, &ReadMassProperties)>,
+    mut rigidbodies: Query<(
+        &Transform,
+        &mut Rigidbody,
+        &Velocity,
+        &ReadMassProperties,
+        &mut ExternalForce,
+        &mut Damping,
+        &mut Sleeping,
+    )>,
     mut dirty_rects: ResMut<DirtyRects>,
+    mut particles: ResMut<Particles>,
+    materials: (Res<Assets<Materials>>, Res<MaterialsHandle>),
 ) {
     puffin::profile_function!();
 
-    for (transform, mut rigidbody, velocity, mass_prop) in &mut rigidbodies {
-        let (width, height) = (rigidbody.width as usize, rigidbody.height as usize);
-        let angle = -transform.rotation.to_euler(EulerRot::XYZ).2;
-        let mut top_left = transform.translation.xy();
-        top_left.y *= -1.;
+    let materials = materials.0.get(&materials.1 .0).unwrap();
+
+    for (transform, mut rigidbody, velocity, mass_prop, mut force, mut damping, mut sleeping) in
+        &mut rigidbodies
+    {
+        if rigidbody.resting {
+            if sleeping.sleeping
+                && !rigidbody.damaged
+                && !terrain_changed(&chunk_manager, &dirty_rects.current, &rigidbody.filled)
+            {
+                continue;
+            }
+
+            // Takes out the atoms it left stamped, and wakes it up to check the new terrain
+            rigidbody.resting = false;
+            unfill(&mut chunk_manager, &mut rigidbody);
+            if sleeping.sleeping {
+                sleeping.sleeping = false;
+            }
+        }
 
-        //let mut stop_count = 0;
-        //let mut total = 0;
+        let (width, height) = (rigidbody.width as usize, rigidbody.height as usize);
+        let (top_left, angle) = Rigidbody::placement(transform);
+        let center_of_mass = transform
+            .transform_point(mass_prop.local_center_of_mass.extend(0.))
+            .xy();
 
         //This fills the chunks with Object atoms
         for (y, x) in (0..height).cartesian_product(0..width) {
@@ -96,28 +251,29 @@ pub fn update_rigidibodies(
             let rotated_atom = rigidbody.atoms[y * width + x];
             if rotated_atom.is_solid() {
                 update_dirty_rects_3x3(&mut dirty_rects.current, chunk_pos);
-                //total += 1;
 
                 if let Some(atom) = chunk_manager.get_mut_atom(chunk_pos) {
+                    //Damaging liquids, like acid and lava, dissolve the atoms they touch
+                    let damage = materials[&*atom].damage;
+                    if atom.is_liquid() && fastrand::f32() < damage * DISSOLVE_CHANCE {
+                        rigidbody.atoms[y * width + x] = Atom::default();
+                        rigidbody.damaged = true;
+                        continue;
+                    }
+
+                    if atom.is_liquid() && !rigidbody.displace {
+                        continue;
+                    }
+
                     if !atom.is_solid() && !atom.is_object() {
                         if atom.is_liquid() || atom.is_powder() {
                             let mut point = pos;
                             point.y *= -1.;
-
-                            let center_of_mass =
-                                mass_prop.local_center_of_mass + transform.translation.xy();
                             let vel_point =
                                 velocity.linear_velocity_at_point(point, center_of_mass);
 
-                            /*Apply impulse to rigidbody
-                            *external_impulse += ExternalImpulse::at_point(
-                                vel_point / mass_prop.mass,
-                                point,
-                                center_of_mass,
-                            );*/
-
                             //Spawn particle
-                            commands.spawn(Particle {
+                            particles.spawn(Particle {
                                 atom: *atom,
                                 velocity: vel_point.normalize_or_zero()
                                     * (vel_point.length() * mass_prop.mass / 1000.).min(16.),
@@ -134,12 +290,88 @@ pub fn update_rigidibodies(
             }
         }
 
-        /*
-        let stop_treshold = total as f32 * STOP_RATE;
-        let new_gravity = ((stop_treshold - stop_count as f32) / stop_treshold).clamp(0., 1.);
+        //An atom is submerged if there's liquid on it, or beside the body on it's row.
+        //Each one pushes the body up with the weight of the liquid it displaces,
+        //unless the liquid dissolves it, so bodies sink in lava and acid while they burn.
+        let mut total = 0;
+        let mut submerged = 0;
+        let mut buoyant = 0;
+        let mut lift = 0.;
+        let mut center_of_buoyancy = Vec2::ZERO;
+        let max = (width + height) as i32;
+        for (y, x) in (0..height).cartesian_product(0..width) {
+            if !rigidbody.atoms[y * width + x].is_solid() {
+                continue;
+            }
+            total += 1;
+
+            let pos = top_left + vec2(x as f32, y as f32).rotate(Vec2::from_angle(angle));
+            let pos = pos.round().as_ivec2();
+            let liquid = chunk_manager
+                .get_atom(&global_to_chunk(pos))
+                .copied()
+                .filter(|atom| atom.is_liquid())
+                .or_else(|| {
+                    [IVec2::X, IVec2::NEG_X]
+                        .into_iter()
+                        .filter_map(|step| past_objects(&chunk_manager, pos, step, max))
+                        .find(|atom| atom.is_liquid())
+                });
+
+            if let Some(liquid) = liquid {
+                submerged += 1;
+                if materials[&liquid].damage > 0. {
+                    continue;
+                }
+                buoyant += 1;
+                lift += materials[&liquid].density * BUOYANCY_GRAVITY;
+                center_of_buoyancy += vec2(pos.x as f32, -pos.y as f32);
+            }
+        }
+
+        *force = if buoyant > 0 {
+            ExternalForce::at_point(
+                vec2(0., lift),
+                center_of_buoyancy / buoyant as f32,
+                center_of_mass,
+            )
+        } else {
+            ExternalForce::default()
+        };
+
+        let fraction = submerged as f32 / total.max(1) as f32;
+        damping.linear_damping = LIQUID_LINEAR_DRAG * fraction;
+        damping.angular_damping = LIQUID_ANGULAR_DRAG * fraction;
+
+        // Damaged rigidbodies are rebuilt after unfilling, so they can't stay stamped
+        rigidbody.resting = sleeping.sleeping && !rigidbody.damaged;
+    }
+}
+
+/// If the atoms stamped by a resting rigidbody, or the ones around them, changed
+fn terrain_changed(
+    chunk_manager: &ChunkManager,
+    dirty_rects: &HashMap<IVec2, URect>,
+    filled: &[ChunkPos],
+) -> bool {
+    filled.iter().any(|pos| {
+        !chunk_manager
+            .get_atom(pos)
+            .is_some_and(|atom| atom.is_object())
+            || dirty_rects
+                .get(&pos.chunk)
+                .is_some_and(|rect| rect.inflate(1).contains(pos.atom))
+    })
+}
 
-        gravity_scale.0 = new_gravity;
-        */
+/// Takes the object atoms stamped by the rigidbody out of the chunks
+fn unfill(chunk_manager: &mut ChunkManager, rigidbody: &mut Rigidbody) {
+    while let Some(chunk_pos) = rigidbody.filled.pop() {
+        if let Some(atom) = chunk_manager.get_mut_atom(chunk_pos) {
+            if atom.is_object() {
+                *atom = Atom::default();
+            }
+        }
     }
 }
 
@@ -148,44 +380,111 @@ pub fn unfill_rigidbodies(
     mut rigidbodies: Query<&mut Rigidbody>,
 ) {
     for mut rigidbody in &mut rigidbodies {
-        while let Some(chunk_pos) = rigidbody.filled.pop() {
-            if let Some(atom) = chunk_manager.get_mut_atom(chunk_pos) {
-                if atom.is_object() {
-                    *atom = Atom::default();
-                }
-            }
+        if !rigidbody.resting {
+            unfill(&mut chunk_manager, &mut rigidbody);
         }
     }
 }
 
-pub fn image_values(image: &Image) -> Vec<f64> {
-    let mut values = vec![];
-    for pixel in image.data.chunks_exact(4) {
-        if pixel[3] > 0 {
-            values.push(1.)
-        } else {
-            values.push(0.)
-        }
-    }
-
-    values
+pub fn atom_values(atoms: &[Atom]) -> Vec<f64> {
+    atoms
+        .iter()
+        .map(|atom| if atom.is_void() { 0. } else { 1. })
+        .collect()
 }
 
-pub fn image_atoms(image: &Image) -> Vec<Atom> {
-    let mut atoms = vec![];
-    for pixel in image.data.chunks_exact(4) {
-        if pixel[3] > 0 {
-            atoms.push(Atom {
-                id: 8,
+/// Gets the atoms of a rigidbody from it's image, with the materials from the mask or image colors.
+/// They are all solid while in the rigidbody, and keep the image colors.
+pub fn body_atoms(
+    def: &RigidbodyDef,
+    image: &Image,
+    mask: Option<&Image>,
+    materials: &Materials,
+) -> Vec<Atom> {
+    let mask = mask.unwrap_or(image);
+
+    image
+        .data
+        .chunks_exact(4)
+        .zip(mask.data.chunks_exact(4))
+        .map(|(pixel, mask_pixel)| {
+            if pixel[3] == 0 {
+                return Atom::default();
+            }
+
+            let id = def
+                .colors
+                .get(mask_pixel.try_into().unwrap())
+                .unwrap_or(def.id);
+            if id == 0 {
+                return Atom::default();
+            }
+
+            Atom {
                 color: pixel.try_into().unwrap(),
-                ..Default::default()
-            });
-        } else {
-            atoms.push(Atom::default())
+                state: AtomState::Solid,
+                ..Atom::new(id, materials)
+            }
+        })
+        .collect()
+}
+
+/// Breaks the rigidbody into particles of it's materials, on the global positions of it's atoms
+pub fn release_atoms(
+    particles: &mut Particles,
+    transform: &Transform,
+    rigidbody: &Rigidbody,
+    materials: &Materials,
+) {
+    let (width, height) = (rigidbody.width as usize, rigidbody.height as usize);
+    let (top_left, angle) = Rigidbody::placement(transform);
+
+    for (y, x) in (0..height).cartesian_product(0..width) {
+        let atom = rigidbody.atoms[y * width + x];
+        if atom.is_void() {
+            continue;
         }
+
+        let pos = top_left + vec2(x as f32, y as f32).rotate(Vec2::from_angle(angle));
+        let angle = fastrand::f32() * std::f32::consts::TAU;
+        particles.spawn(released_particle(
+            atom,
+            pos,
+            vec2(angle.cos(), angle.sin()) * fastrand::f32() * 2.,
+            materials,
+        ));
     }
+}
 
-    atoms
+/// A particle of a atom leaving a rigidbody, that goes back to it's material state
+pub fn released_particle(atom: Atom, pos: Vec2, velocity: Vec2, materials: &Materials) -> Particle {
+    Particle {
+        atom: Atom {
+            state: materials[atom.id].default_state,
+            ..atom
+        },
+        velocity,
+        pos: pos.round(),
+        ..Default::default()
+    }
+}
+
+/// Image of the rigidbody atoms, void atoms are transparent
+pub fn rigidbody_image(atoms: &[Atom], width: u8, height: u8) -> Image {
+    Image::new(
+        Extent3d {
+            width: width as u32,
+            height: height as u32,
+            ..Default::default()
+        },
+        TextureDimension::D2,
+        atoms
+            .iter()
+            .flat_map(|atom| if atom.is_void() { [0; 4] } else { atom.color })
+            .collect(),
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+    )
 }
 
 pub fn get_collider(values: &[f64], width: u32, height: u32) -> Option<Collider> {
@@ -218,7 +517,8 @@ pub fn get_collider(values: &[f64], width: u32, height: u32) -> Option<Collider>
 pub struct RigidbodyPlugin;
 impl Plugin for RigidbodyPlugin {
     fn build(&self, app: &mut App) {
-        app //.add_systems(Startup, load_images)
+        app.init_resource::<RigidbodyAssets>()
+            .add_systems(OnEnter(GameState::Loading), rigidbodies_setup)
             .add_systems(Update, add_rigidbodies.run_if(in_state(GameState::Game)))
             .add_systems(
                 FixedUpdate,
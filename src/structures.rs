@@ -0,0 +1,376 @@
+use crate::prelude::*;
+
+/// A hand made structure, loaded from a RON file in `assets/structures`
+#[derive(Clone, Debug, Deserialize)]
+pub struct StructureDef {
+    /// Image path, relative to the assets folder
+    pub image: String,
+    #[serde(default)]
+    pub placement: StructurePlacement,
+
+    /// The world is split in square cells of this size, in atoms
+    pub cell_size: u32,
+    /// Chance of the structure being on each cell
+    pub frequency: f64,
+    /// Depth range, in global atoms, where the structure can be
+    #[serde(default)]
+    pub min_depth: Option<f64>,
+    #[serde(default)]
+    pub max_depth: Option<f64>,
+    /// Names of the biomes the structure can be, if none it can be anywhere
+    #[serde(default)]
+    pub biomes: Option<Vec<String>>,
+
+    /// Material of opaque pixels, if the colors don't map them
+    pub id: u8,
+    /// Pixel colors to materials, a id of 0 carves air
+    #[serde(default)]
+    pub colors: ColorMapping,
+    /// How many atoms a surface structure goes into the ground
+    #[serde(default)]
+    pub sink: i32,
+
+    #[serde(default)]
+    pub rigidbodies: Vec<StructureRigidbody>,
+    #[serde(default)]
+    pub items: Vec<StructureItem>,
+    #[serde(default)]
+    pub joints: Vec<StructureJoint>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum StructurePlacement {
+    /// Sits on top of the first ground found going down the cell
+    #[default]
+    Surface,
+    /// Anywhere inside the terrain
+    Buried,
+}
+
+/// A rigidbody spawned with the structure, the position is relative to it's top left
+#[derive(Clone, Debug, Deserialize)]
+pub struct StructureRigidbody {
+    /// Name of the definition in `assets/rigidbodies`
+    pub body: String,
+    pub pos: (i32, i32),
+}
+
+/// A joint connecting one of the structure rigidbodies, anchors are relative to the top left
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct StructureJoint {
+    pub kind: JointKind,
+    /// Index of the rigidbody in the structure `rigidbodies`
+    pub body: usize,
+    /// Anchor on the rigidbody image
+    pub anchor: (i32, i32),
+    pub to: StructureJointTo,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum StructureJointTo {
+    /// A atom on the structure image, the joint breaks when it's destroyed
+    Terrain(i32, i32),
+    /// Another of the structure rigidbodies by index, and the anchor on it's image
+    Body(usize, (i32, i32)),
+}
+
+/// A atom pickup spawned with the structure, the position is relative to it's top left
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct StructureItem {
+    pub id: u8,
+    pub number: u16,
+    pub pos: (i32, i32),
+}
+
+/// Entities to spawn from the chunk with the structure origin, made when it's generated
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StructureSpawn {
+    Rigidbody(String, Vec2),
+    Pickup(Pickup),
+    /// A joint, the structure global top left, and the index of the
+    /// structure first rigidbody in the chunk rigidbody spawns
+    Joint(StructureJoint, IVec2, usize),
+}
+
+/// Structure definitions and their loading images, waited on before the game starts
+#[derive(Resource, Default)]
+pub struct StructureAssets(pub Vec<(StructureDef, Handle<Image>)>);
+
+impl StructureAssets {
+    pub fn is_loaded(&self, asset_server: &AssetServer) -> bool {
+        self.0
+            .iter()
+            .all(|(_, image)| asset_server.is_loaded(image))
+    }
+
+    /// Gets the structures pixels, to be used by the generator
+    pub fn build(&self, images: &Assets<Image>) -> Vec<Structure> {
+        self.0
+            .iter()
+            .filter_map(|(def, image)| {
+                let image = images.get(image)?;
+                Some(Structure::new(def.clone(), image))
+            })
+            .collect()
+    }
+}
+
+/// Reads all the structure definitions, in the same order on every platform
+pub fn load_structure_defs() -> Vec<StructureDef> {
+    let Ok(dir) = std::fs::read_dir("assets/structures") else {
+        return vec![];
+    };
+
+    let mut paths: Vec<_> = dir
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ron"))
+        .collect();
+    // Keeps the structures order, and with it the world, the same on every platform
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let bytes = std::fs::read(&path).unwrap();
+            ron::de::from_bytes::<StructureDef>(&bytes).unwrap()
+        })
+        .collect()
+}
+
+pub fn structures_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let mut structures = vec![];
+
+    for def in load_structure_defs() {
+        let image: Handle<Image> = asset_server.load(&def.image);
+        structures.push((def, image));
+    }
+
+    commands.insert_resource(StructureAssets(structures));
+}
+
+/// A loaded structure
+pub struct Structure {
+    pub def: StructureDef,
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl Structure {
+    pub fn new(def: StructureDef, image: &Image) -> Self {
+        Self {
+            def,
+            width: image.width() as i32,
+            height: image.height() as i32,
+            pixels: image
+                .data
+                .chunks_exact(4)
+                .map(|pixel| pixel.try_into().unwrap())
+                .collect(),
+        }
+    }
+
+    /// Gets the top left global position of the structure on a cell, if there is one
+    pub fn origin(&self, cell: IVec2, seed: u32, generator: &Generator) -> Option<IVec2> {
+        let def = &self.def;
+        let rand = |salt: u32| hash(cell, seed.wrapping_add(salt));
+
+        if rand(0) >= def.frequency {
+            return None;
+        }
+
+        let cell_size = def.cell_size as i32;
+        let cell_pos = cell * cell_size;
+        let x = cell_pos.x + (rand(1) * cell_size as f64) as i32;
+
+        let bottom_center = match def.placement {
+            StructurePlacement::Surface => {
+                // Goes down the cell in steps looking for the first ground,
+                // then bisects between the last air and it to find the surface
+                let air = |y: i32| generator.material_at(ivec2(x, y)).is_none();
+                let end = cell_pos.y + cell_size - 1;
+                let mut prev = cell_pos.y - 1;
+                let mut prev_air = air(prev);
+                let mut found = None;
+                let mut y = cell_pos.y;
+                while y <= end {
+                    let y_air = air(y);
+                    if prev_air && !y_air {
+                        found = Some((prev, y));
+                        break;
+                    }
+                    prev = y;
+                    prev_air = y_air;
+                    y = if y == end {
+                        end + 1
+                    } else {
+                        (y + STRUCTURE_SEARCH_STEP).min(end)
+                    };
+                }
+                let (mut lo, mut hi) = found?;
+                while hi - lo > 1 {
+                    let mid = (lo + hi) / 2;
+                    if air(mid) {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                ivec2(x, hi + def.sink)
+            }
+            StructurePlacement::Buried => {
+                let y = cell_pos.y + (rand(2) * cell_size as f64) as i32;
+                let center = ivec2(x, y - self.height / 2);
+                generator.material_at(center)?;
+                ivec2(x, y)
+            }
+        };
+
+        let depth = bottom_center.y as f64;
+        if def.min_depth.is_some_and(|min| depth < min)
+            || def.max_depth.is_some_and(|max| depth >= max)
+        {
+            return None;
+        }
+
+        if let Some(biomes) = &def.biomes {
+            let biome = generator.terrain(bottom_center).biome;
+            let biome = generator.config.biomes.get(biome)?;
+            if !biomes.contains(&biome.name) {
+                return None;
+            }
+        }
+
+        Some(bottom_center - ivec2(self.width / 2, self.height))
+    }
+
+    /// Stamps the structure on the chunk atoms, adding what needs to be spawned
+    pub fn place(&self, chunk: &mut GenChunk, seed: u32, generator: &Generator) {
+        let def = &self.def;
+        if def.cell_size == 0 {
+            return;
+        }
+
+        let (index, materials) = (chunk.index, chunk.materials);
+
+        let cell_size = def.cell_size as i32;
+        let chunk_min = index * CHUNK_LENGHT as i32;
+        let chunk_max = chunk_min + CHUNK_LENGHT as i32;
+
+        // Surface structures can be moved down a whole cell from where they started
+        let reach = ivec2(self.width, self.height + cell_size);
+        let min_cell = (chunk_min - reach).div_euclid(IVec2::splat(cell_size));
+        let max_cell = (chunk_max + reach).div_euclid(IVec2::splat(cell_size));
+
+        for cell_y in min_cell.y..=max_cell.y {
+            for cell_x in min_cell.x..=max_cell.x {
+                let cell = ivec2(cell_x, cell_y);
+
+                // Checks if the x range can reach the chunk before doing the expensive search
+                let x = cell_x * cell_size;
+                if x - self.width > chunk_max.x || x + cell_size + self.width < chunk_min.x {
+                    continue;
+                }
+
+                let Some(origin) = self.origin(cell, seed, generator) else {
+                    continue;
+                };
+
+                let min = (origin - chunk_min).max(IVec2::ZERO);
+                let max = (origin + ivec2(self.width, self.height) - chunk_min)
+                    .min(IVec2::splat(CHUNK_LENGHT as i32));
+
+                for y in min.y..max.y {
+                    for x in min.x..max.x {
+                        let image_pos = ivec2(x, y) + chunk_min - origin;
+                        let color = self.pixels[(image_pos.y * self.width + image_pos.x) as usize];
+                        if color[3] == 0 {
+                            continue;
+                        }
+
+                        let id = def.colors.get(color).unwrap_or(def.id);
+                        chunk.atoms[ivec2(x, y).d1()] = if id == 0 {
+                            Atom::default()
+                        } else {
+                            Atom {
+                                id,
+                                state: materials[id].default_state,
+                                color,
+                                ..Default::default()
+                            }
+                        };
+                    }
+                }
+
+                // Only spawns from the chunk the structure starts at, so it's done once
+                if global_to_chunk(origin).chunk == index {
+                    let first_body = chunk
+                        .spawns
+                        .iter()
+                        .filter(|spawn| matches!(spawn, StructureSpawn::Rigidbody(..)))
+                        .count();
+
+                    for rigidbody in &def.rigidbodies {
+                        let pos = origin + ivec2(rigidbody.pos.0, rigidbody.pos.1);
+                        chunk.spawns.push(StructureSpawn::Rigidbody(
+                            rigidbody.body.clone(),
+                            pos.as_vec2(),
+                        ));
+                    }
+
+                    for item in &def.items {
+                        let pos = origin + ivec2(item.pos.0, item.pos.1);
+                        chunk.spawns.push(StructureSpawn::Pickup(Pickup {
+                            item: Item::Atom(Atom::new(item.id, materials)),
+                            number: item.number,
+                            pos: pos.as_vec2(),
+                        }));
+                    }
+
+                    for joint in &def.joints {
+                        chunk
+                            .spawns
+                            .push(StructureSpawn::Joint(*joint, origin, first_body));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Places all the loaded structures on the chunk atoms, in order
+pub fn place_structures(chunk: &mut GenChunk, generator: &Generator) {
+    for (i, structure) in generator.structures.iter().enumerate() {
+        let seed = generator
+            .config
+            .seed
+            .wrapping_add((i as u32 + 1).wrapping_mul(0x85EB_CA6B));
+        structure.place(chunk, seed, generator);
+    }
+}
+
+/// Stamps the prefab structures, after the deposits and liquids
+pub struct StructuresStage;
+impl GenStage for StructuresStage {
+    fn name(&self) -> &str {
+        "structures"
+    }
+
+    fn run(&self, chunk: &mut GenChunk, generator: &Generator) {
+        place_structures(chunk, generator);
+    }
+}
+
+pub struct StructuresPlugin;
+impl Plugin for StructuresPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StructureAssets>()
+            .init_resource::<GenPipeline>()
+            .add_systems(OnEnter(GameState::Loading), structures_setup);
+
+        app.world_mut()
+            .resource_mut::<GenPipeline>()
+            .insert_after("liquids", StructuresStage);
+    }
+}